@@ -0,0 +1,58 @@
+//! Measures how fast the file exporters write records decoded from a synthetic
+//! firehose, into a sink so only serialization is measured.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use skystreamer::{
+    testing::FirehoseGenerator,
+    types::commit::{Commit, Record},
+};
+use skystreamer_bin::exporter::{CsvExporter, Exporter, JsonlExporter, RecordJsonlExporter};
+
+/// Creates an exporter writing into a sink
+type NewExporter = fn() -> Box<dyn Exporter>;
+
+fn exporters(c: &mut Criterion) {
+    const COMMITS: usize = 1000;
+    let mut generator = FirehoseGenerator::new();
+    let records = futures::executor::block_on(async {
+        let mut records = vec![];
+        for _ in 0..COMMITS {
+            let commit = Commit::from(generator.next_commit());
+            records.extend(commit.extract_records().await);
+        }
+        records
+    });
+
+    let exporters: [(&str, NewExporter); 3] = [
+        ("jsonl", || Box::new(JsonlExporter::new(tokio::io::sink()))),
+        ("record_jsonl", || {
+            Box::new(RecordJsonlExporter::new(tokio::io::sink()))
+        }),
+        ("csv", || Box::new(CsvExporter::new(tokio::io::sink()))),
+    ];
+
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("exporters");
+    group.throughput(Throughput::Elements(records.len() as u64));
+    for (name, exporter) in exporters {
+        group.bench_with_input(
+            BenchmarkId::new("export_record", name),
+            &records,
+            |b, records: &Vec<Record>| {
+                // The CSV writer flushes with a blocking executor when dropped, which never
+                // finishes once the sink runs out of cooperative scheduling budget
+                b.to_async(&runtime).iter(|| {
+                    tokio::task::unconstrained(async {
+                        let mut exporter = exporter();
+                        for record in records {
+                            exporter.export_record(record).await.unwrap();
+                        }
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, exporters);
+criterion_main!(benches);
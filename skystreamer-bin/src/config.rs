@@ -3,6 +3,7 @@ use color_eyre::Result;
 use surrealdb::{opt::auth::Root, Surreal};
 
 use crate::Consumer;
+use skystreamer::{filter::RecordFilter, types::operation::Action};
 // use crate::
 #[derive(Debug, ValueEnum, Clone)]
 pub enum SurrealAuthType {
@@ -18,6 +19,8 @@ pub enum SurrealAuthType {
 pub enum ExporterType {
     /// Export to a JSONL (JSON Lines) file
     Jsonl,
+    /// Export every kind of record, not just posts, to a JSONL file
+    RecordsJsonl,
     /// Export to a CSV file
     Csv,
     /// Export to a SurrealDB instance
@@ -36,6 +39,7 @@ pub struct FileExporterOptions {
         short = 'o',
         long,
         required_if_eq("exporter", "jsonl"),
+        required_if_eq("exporter", "records-jsonl"),
         required_if_eq("exporter", "csv"),
         env = "FILE_EXPORT_PATH",
         group = "file_exporter"
@@ -43,6 +47,54 @@ pub struct FileExporterOptions {
     pub file_path: Option<String>,
 }
 
+#[derive(Parser, Debug, Clone)]
+pub struct FilterOptions {
+    /// Only export records of these collections, e.g. `app.bsky.feed.post` or `app.bsky.graph.*`.
+    /// Together with `--action`, this replaces the exporter's default selection of records.
+    #[clap(long = "collection", value_delimiter = ',', env = "FILTER_COLLECTIONS")]
+    pub collections: Vec<String>,
+
+    /// Only export records changed by these actions
+    #[clap(
+        long = "action",
+        value_delimiter = ',',
+        value_parser = ["create", "update", "delete"],
+        env = "FILTER_ACTIONS"
+    )]
+    pub actions: Vec<String>,
+
+    /// Only export records of these DIDs
+    #[clap(long = "did", value_delimiter = ',', env = "FILTER_DIDS")]
+    pub dids: Vec<String>,
+
+    /// Never export records of these DIDs
+    #[clap(
+        long = "exclude-did",
+        value_delimiter = ',',
+        env = "FILTER_EXCLUDE_DIDS"
+    )]
+    pub exclude_dids: Vec<String>,
+}
+
+impl FilterOptions {
+    /// Build the filter for the stream, starting from the exporter's `default`
+    pub fn filter(&self, default: RecordFilter) -> RecordFilter {
+        let mut filter = if self.collections.is_empty() && self.actions.is_empty() {
+            default
+        } else {
+            let mut filter = RecordFilter::new().collections(self.collections.iter().cloned());
+            if !self.actions.is_empty() {
+                filter = filter.actions(self.actions.iter().map(|a| Action::from(a.as_str())));
+            }
+            filter
+        };
+        if !self.dids.is_empty() {
+            filter = filter.allow_dids(self.dids.iter().cloned());
+        }
+        filter.deny_dids(self.exclude_dids.iter().cloned())
+    }
+}
+
 #[derive(Parser, Debug, Clone)]
 pub struct SurrealDbConn {
     /// SurrealDB endpoint
@@ -185,6 +237,8 @@ pub struct Config {
     pub exporter: ExporterType,
     #[clap(flatten)]
     pub file_exporter: FileExporterOptions,
+    #[clap(flatten)]
+    pub filter: FilterOptions,
 
     #[clap(
         short = 'R',
@@ -193,6 +247,10 @@ pub struct Config {
         env = "ATPROTO_RELAY"
     )]
     pub atproto_relay: String,
+
+    /// How many commits to decode in parallel. Defaults to the number of CPU cores.
+    #[clap(short = 'w', long, env = "DECODE_WORKERS")]
+    pub decode_workers: Option<usize>,
 }
 
 impl Config {
@@ -204,6 +262,12 @@ impl Config {
                 Box::new(crate::exporter::JsonlExporter::new(file))
                     as Box<dyn crate::exporter::Exporter>
             }
+            ExporterType::RecordsJsonl => {
+                let file_path = self.file_exporter.file_path.as_ref().unwrap();
+                let file = tokio::fs::File::create(file_path).await?;
+                Box::new(crate::exporter::RecordJsonlExporter::new(file))
+                    as Box<dyn crate::exporter::Exporter>
+            }
             ExporterType::Csv => {
                 let file_path = self.file_exporter.file_path.as_ref().unwrap();
                 let file = tokio::fs::File::create(file_path).await?;
@@ -220,6 +284,12 @@ impl Config {
             }
         };
 
-        Ok(Consumer::new(exporter, &self.atproto_relay))
+        let filter = self.filter.filter(exporter.filter());
+        let decode_workers = self.decode_workers.unwrap_or_else(|| {
+            std::thread::available_parallelism().map_or(1, std::num::NonZeroUsize::get)
+        });
+        Ok(Consumer::new(exporter, &self.atproto_relay)
+            .with_filter(filter)
+            .with_decode_workers(decode_workers))
     }
 }
@@ -8,7 +8,8 @@ use chrono::NaiveDateTime;
 use color_eyre::eyre::OptionExt;
 use color_eyre::Result;
 use ipld_core::ipld::Ipld;
-use skystreamer::types::Post as SPost;
+use skystreamer::filter::RecordFilter;
+use skystreamer::types::{commit::Record, operation::Action, Post as SPost};
 use std::sync::{Arc, OnceLock};
 use surrealdb::{Connection, Surreal};
 use tokio::io::AsyncWriteExt;
@@ -181,11 +182,36 @@ impl Exporter for DryRunExporter {
         tracing::info!("Dry run: {:?}", post);
         Ok(())
     }
+
+    async fn export_record(&mut self, record: &Record) -> Result<()> {
+        tracing::info!("Dry run: {:?}", record);
+        Ok(())
+    }
+
+    fn filter(&self) -> RecordFilter {
+        RecordFilter::new()
+    }
 }
 
 #[async_trait::async_trait]
-pub trait Exporter {
+pub trait Exporter: Send {
     async fn export(&mut self, post: &SPost) -> Result<()>;
+
+    /// Export any kind of record. By default, only posts are exported.
+    async fn export_record(&mut self, record: &Record) -> Result<()> {
+        match record {
+            Record::Post(post) => self.export(post).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// The records this exporter exports, so the rest are dropped before being decoded.
+    /// By default, created and updated posts.
+    fn filter(&self) -> RecordFilter {
+        RecordFilter::new()
+            .collections(["app.bsky.feed.post"])
+            .actions([Action::Create, Action::Update])
+    }
 }
 
 pub struct SurrealDbExporter<C: Connection> {
@@ -369,6 +395,37 @@ impl<W: tokio::io::AsyncWrite + Unpin + Send> Exporter for JsonlExporter<W> {
     }
 }
 
+/// Exports every kind of record into JSONL, using the tagged schema of [`Record`]
+pub struct RecordJsonlExporter<W: tokio::io::AsyncWrite + Unpin> {
+    writer: W,
+}
+
+impl<W: tokio::io::AsyncWrite + Unpin> RecordJsonlExporter<W> {
+    pub fn new(writer: W) -> Self {
+        RecordJsonlExporter { writer }
+    }
+}
+
+#[async_trait::async_trait]
+impl<W: tokio::io::AsyncWrite + Unpin + Send> Exporter for RecordJsonlExporter<W> {
+    async fn export(&mut self, post: &SPost) -> Result<()> {
+        self.export_record(&Record::Post(Box::new(post.clone())))
+            .await
+    }
+
+    async fn export_record(&mut self, record: &Record) -> Result<()> {
+        let json = serde_json::to_string(record)?;
+        self.writer
+            .write_all(format!("{}\n", json).as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    fn filter(&self) -> RecordFilter {
+        RecordFilter::new()
+    }
+}
+
 pub struct CsvExporter<W: tokio::io::AsyncWrite + Unpin> {
     writer: W,
 }
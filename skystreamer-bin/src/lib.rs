@@ -0,0 +1,74 @@
+//! The collector behind the `skystreamer` binary, exposed as a library so its
+//! exporters can be benchmarked.
+pub mod config;
+pub mod exporter;
+pub mod surreal_types;
+use color_eyre::Result;
+use futures::StreamExt;
+use skystreamer::{filter::RecordFilter, stream::EventStream, RepoSubscription};
+use update_rate::RateCounter;
+
+pub struct Consumer {
+    rate_counter: update_rate::DiscreteRateCounter,
+    exporter: Box<dyn exporter::Exporter>,
+    pub atproto_relay: String,
+    filter: RecordFilter,
+    decode_workers: usize,
+}
+
+impl Consumer {
+    pub fn new(exporter: Box<dyn exporter::Exporter>, relay: &str) -> Self {
+        Consumer {
+            rate_counter: update_rate::DiscreteRateCounter::new(50),
+            exporter,
+            atproto_relay: relay.to_string(),
+            filter: RecordFilter::new(),
+            decode_workers: 1,
+        }
+    }
+
+    /// Decode up to `workers` commits in parallel
+    pub fn with_decode_workers(mut self, workers: usize) -> Self {
+        self.decode_workers = workers;
+        self
+    }
+
+    /// Only stream the records kept by `filter`
+    pub fn with_filter(mut self, filter: RecordFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub async fn start(&mut self) -> Result<()> {
+        let subscription = RepoSubscription::new(&self.atproto_relay).await?;
+        let mut event_stream = EventStream::new(subscription)
+            .with_filter(self.filter.clone())
+            .with_parallelism(self.decode_workers);
+        let stream = event_stream.stream().await?;
+
+        // let post_stream = PostStream::new(subscription);
+
+        // let mut post_stream = post_stream.await;
+
+        futures::pin_mut!(stream);
+
+        while let Some(record) = stream.next().await {
+            if let Err(e) = self.exporter.export_record(&record).await {
+                tracing::error!("Failed to export {}: {}", record.kind(), e);
+            }
+            self.update_stats();
+        }
+
+        Ok(())
+    }
+
+    pub fn update_stats(&mut self) {
+        self.rate_counter.update();
+        if self.rate_counter.rate_age_cycles() == 0 {
+            tracing::info!(
+                "Ingest rate: {rate:.2} items/s",
+                rate = self.rate_counter.rate()
+            );
+        }
+    }
+}
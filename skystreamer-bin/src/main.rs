@@ -1,64 +1,11 @@
 // use anyhow::{anyhow, Result};
 // use crate::types::Frame;
-mod config;
-mod exporter;
-mod surreal_types;
 use clap::Parser;
 use color_eyre::Result;
-use futures::StreamExt;
-use skystreamer::{stream::EventStream, RepoSubscription};
+use skystreamer_bin::config;
 // use std::sync::Arc;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
-use update_rate::RateCounter;
-
-pub struct Consumer {
-    rate_counter: update_rate::DiscreteRateCounter,
-    exporter: Box<dyn exporter::Exporter>,
-    pub atproto_relay: String,
-}
-
-impl Consumer {
-    pub fn new(exporter: Box<dyn exporter::Exporter>, relay: &str) -> Self {
-        Consumer {
-            rate_counter: update_rate::DiscreteRateCounter::new(50),
-            exporter,
-            atproto_relay: relay.to_string(),
-        }
-    }
-
-    pub async fn start(&mut self) -> Result<()> {
-        let subscription = RepoSubscription::new(&self.atproto_relay).await.unwrap();
-        let mut event_stream = EventStream::new(subscription);
-        let stream = event_stream.stream().await?;
-
-        // let post_stream = PostStream::new(subscription);
-
-        // let mut post_stream = post_stream.await;
-
-        futures::pin_mut!(stream);
-
-        while let Some(skystreamer::types::commit::Record::Post(post)) = stream.next().await {
-            // if let skystreamer::types::commit::Record::Post(post) = post {
-            if let Err(e) = self.exporter.export(&post).await {
-                tracing::error!("Failed to export post: {}", e);
-            }
-            self.update_stats();
-        }
-
-        Ok(())
-    }
-
-    pub fn update_stats(&mut self) {
-        self.rate_counter.update();
-        if self.rate_counter.rate_age_cycles() == 0 {
-            tracing::info!(
-                "Ingest rate: {rate:.2} items/s",
-                rate = self.rate_counter.rate()
-            );
-        }
-    }
-}
 
 // // #[derive(Debug)]
 // pub struct FirehoseConsumer {
@@ -209,7 +156,7 @@ async fn main() -> Result<()> {
     // });
     // config.consumer().await?.start().await?;
 
-    let config = crate::config::Config::parse();
+    let config = config::Config::parse();
     let mut consumer = config.consumer().await?;
 
     // ctrlc::set_handler(move || {
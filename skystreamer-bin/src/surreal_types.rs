@@ -39,11 +39,12 @@ impl From<&skystreamer::types::Embed> for Embed {
         match embed {
             skystreamer::types::Embed::Media(media) => Embed::Media(media.clone()),
             skystreamer::types::Embed::External(link) => Embed::External(link.clone()),
-            skystreamer::types::Embed::Record(record) => {
-                Embed::Record(RecordId::from_table_key(POSTS_TABLE, record.to_string()))
-            }
+            skystreamer::types::Embed::Record(record) => Embed::Record(RecordId::from_table_key(
+                POSTS_TABLE,
+                record.cid.to_string(),
+            )),
             skystreamer::types::Embed::RecordWithMedia(record, media) => Embed::RecordWithMedia {
-                record: RecordId::from_table_key(POSTS_TABLE, record.to_string()),
+                record: RecordId::from_table_key(POSTS_TABLE, record.cid.to_string()),
                 media: *media.clone(),
             },
             _ => Embed::Unknown,
@@ -218,8 +219,8 @@ impl From<skystreamer::types::Post> for SurrealPostRep {
             embed: post.embed.as_ref().map(|embed| embed.into()),
             language: post.language,
             reply: post.reply.as_ref().map(|reply| ReplyRef {
-                reply_parent: RecordId::from_table_key(POSTS_TABLE, reply.parent.to_string()),
-                reply_root: RecordId::from_table_key(POSTS_TABLE, reply.root.to_string()),
+                reply_parent: RecordId::from_table_key(POSTS_TABLE, reply.parent.cid.to_string()),
+                reply_root: RecordId::from_table_key(POSTS_TABLE, reply.root.cid.to_string()),
             }),
             tags: post.tags,
             labels: post.labels,
@@ -126,6 +126,33 @@ async fn main() -> Result<()> {
                     type_counter.with_label_values(&["profile"]).inc();
                     // todo
                 }
+                Record::FeedGenerator(_) => {
+                    type_counter.with_label_values(&["feed_generator"]).inc();
+                }
+                Record::Threadgate(_) => {
+                    type_counter.with_label_values(&["threadgate"]).inc();
+                }
+                Record::Postgate(_) => {
+                    type_counter.with_label_values(&["postgate"]).inc();
+                }
+                Record::List(_) => {
+                    type_counter.with_label_values(&["list"]).inc();
+                }
+                Record::ListBlock(_) => {
+                    type_counter.with_label_values(&["list_block"]).inc();
+                }
+                Record::StarterPack(_) => {
+                    type_counter.with_label_values(&["starter_pack"]).inc();
+                }
+                Record::LabelerService(_) => {
+                    type_counter.with_label_values(&["labeler_service"]).inc();
+                }
+                Record::ChatDeclaration(_) => {
+                    type_counter.with_label_values(&["chat_declaration"]).inc();
+                }
+                Record::Delete(_) => {
+                    type_counter.with_label_values(&["delete"]).inc();
+                }
                 _ => {
                     // todo
                 }
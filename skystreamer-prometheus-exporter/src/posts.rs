@@ -204,6 +204,7 @@ impl PostsRegistry {
             let has_alt_text = post_media.iter().any(|media| match media {
                 skystreamer::types::Media::Image(i) => !i.alt.is_empty(),
                 skystreamer::types::Media::Video(v) => v.alt.is_some(),
+                skystreamer::types::Media::External(_) => false,
             });
 
             if has_alt_text {
@@ -213,12 +214,14 @@ impl PostsRegistry {
         {
             // Posts with media by type
             // let post_media = post.get_post_media(post);
-            if !post_media.is_empty() {
-                // get the first media type, because images and videos are mutually exclusive
-                let media_type = match post_media.first().unwrap() {
-                    skystreamer::types::Media::Image(_) => "image",
-                    skystreamer::types::Media::Video(_) => "video",
-                };
+            // get the first media type, because images and videos are mutually exclusive
+            let media_type = post_media.iter().find_map(|media| match media {
+                skystreamer::types::Media::Image(_) => Some("image"),
+                skystreamer::types::Media::Video(_) => Some("video"),
+                // link cards are not media, `get_post_media` never returns them
+                skystreamer::types::Media::External(_) => None,
+            });
+            if let Some(media_type) = media_type {
                 self.media_posts_counter
                     .with_label_values(&[media_type])
                     .inc();
@@ -235,10 +238,11 @@ impl PostsRegistry {
 
         // Posts by external media's domain name
         {
-            let external_link = post.embed.clone().and_then(|embed| match embed {
-                skystreamer::types::Embed::External(e) => Some(Url::parse(&e.uri)),
-                _ => None,
-            });
+            let external_link = post
+                .embed
+                .as_ref()
+                .and_then(|embed| embed.external())
+                .map(|e| Url::parse(&e.uri));
 
             // // Let's clean up some old domains to keep the response sizes down
             // if self.domain_reset_time < tokio::time::Instant::now() {
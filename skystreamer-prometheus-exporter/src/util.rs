@@ -28,7 +28,6 @@ pub fn handle_language(lang: &str) -> Option<String> {
 }
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
     use tracing_test::traced_test;
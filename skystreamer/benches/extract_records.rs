@@ -0,0 +1,86 @@
+//! Compares decoding a commit's CAR slice once per commit against once per operation.
+use atrium_api::{
+    app::bsky::feed::post::RecordData as PostData,
+    com::atproto::sync::subscribe_repos::{Commit as ACommit, CommitData, RepoOpData},
+    types::{string::Datetime, CidLink},
+};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use skystreamer::{
+    testing::{car, cid_for},
+    types::commit::{Commit, Record},
+};
+
+/// Build a commit with `ops` post creations and a CAR slice containing their records
+fn synthetic_commit(ops: usize) -> ACommit {
+    let mut blocks = vec![];
+    let mut repo_ops = vec![];
+    for i in 0..ops {
+        let post: atrium_api::app::bsky::feed::post::Record = PostData {
+            created_at: Datetime::now(),
+            embed: None,
+            entities: None,
+            facets: None,
+            labels: None,
+            langs: None,
+            reply: None,
+            tags: None,
+            text: format!("synthetic post number {i}"),
+        }
+        .into();
+        let block = serde_ipld_dagcbor::to_vec(&post).unwrap();
+        let cid = cid_for(&block);
+        repo_ops.push(
+            RepoOpData {
+                action: "create".to_string(),
+                cid: Some(CidLink(cid)),
+                path: format!("app.bsky.feed.post/{i:013}"),
+            }
+            .into(),
+        );
+        blocks.push((cid, block));
+    }
+
+    let root = blocks[0].0;
+
+    CommitData {
+        blobs: vec![],
+        blocks: car(root, &blocks),
+        commit: CidLink(root),
+        ops: repo_ops,
+        prev: None,
+        rebase: false,
+        repo: "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+        rev: "3l6oveex3ii2l".to_string(),
+        seq: 1,
+        since: None,
+        time: Datetime::now(),
+        too_big: false,
+    }
+    .into()
+}
+
+fn extract_records(c: &mut Criterion) {
+    let mut group = c.benchmark_group("extract_records");
+    for ops in [10, 100, 500] {
+        let commit = Commit::from(synthetic_commit(ops));
+
+        group.bench_with_input(BenchmarkId::new("per_op", ops), &commit, |b, commit| {
+            b.iter(|| {
+                futures::executor::block_on(async {
+                    let mut records = vec![];
+                    for op in &commit.operations {
+                        records.extend(Record::from_op(op, commit.inner()).await.unwrap());
+                    }
+                    records
+                })
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("per_commit", ops), &commit, |b, commit| {
+            b.iter(|| futures::executor::block_on(commit.extract_records()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, extract_records);
+criterion_main!(benches);
@@ -0,0 +1,80 @@
+//! Measures how fast an `EventStream` decodes a synthetic firehose into records,
+//! inline and on parallel decode workers.
+//!
+//! The exporters are benchmarked in `skystreamer-bin`, which they belong to.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use futures::StreamExt;
+use skystreamer::{
+    stream::EventStream,
+    testing::{FirehoseGenerator, OperationMix},
+    types::{Frame, Subscription},
+    Result,
+};
+
+/// Replays frames generated ahead of time, so only decoding is measured
+struct Frames(std::vec::IntoIter<Frame>);
+
+impl Subscription for Frames {
+    async fn next(&mut self) -> Option<Result<Frame>> {
+        self.0.next().map(Ok)
+    }
+
+    async fn resume(&mut self, _cursor: Option<i64>) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn event_stream(c: &mut Criterion) {
+    const COMMITS: u64 = 1000;
+    let mixes = [
+        ("default", OperationMix::default()),
+        (
+            "posts_with_embeds",
+            OperationMix {
+                posts: 1,
+                embeds: 1,
+                likes: 0,
+                follows: 0,
+                deletes: 0,
+            },
+        ),
+    ];
+
+    let mut group = c.benchmark_group("event_stream");
+    group.throughput(Throughput::Elements(COMMITS));
+    for (name, mix) in mixes {
+        let mut generator = FirehoseGenerator::new().mix(mix);
+        let frames = (0..COMMITS)
+            .map(|_| generator.next_frame().unwrap())
+            .collect::<Vec<_>>();
+
+        group.bench_with_input(BenchmarkId::new("records", name), &frames, |b, frames| {
+            b.iter(|| {
+                futures::executor::block_on(async {
+                    let mut binding = EventStream::new(Frames(frames.clone().into_iter()));
+                    binding.stream().await.unwrap().count().await
+                })
+            })
+        });
+
+        let workers = std::thread::available_parallelism().map_or(4, usize::from);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        group.bench_with_input(
+            BenchmarkId::new(format!("records_parallel_{workers}"), name),
+            &frames,
+            |b, frames| {
+                b.iter(|| {
+                    runtime.block_on(async {
+                        let mut binding = EventStream::new(Frames(frames.clone().into_iter()))
+                            .with_parallelism(workers);
+                        binding.stream().await.unwrap().count().await
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, event_stream);
+criterion_main!(benches);
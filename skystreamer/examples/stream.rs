@@ -1,6 +1,6 @@
 // use color_eyre::Result;
 use futures::{pin_mut, StreamExt};
-use skystreamer::{stream::EventStream, RepoSubscription};
+use skystreamer::{stream::EventStream, types::ipld::to_dag_json, RepoSubscription};
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -17,7 +17,11 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     while let Some(record) = event_stream.next().await {
         // stream unknown record types
         if let skystreamer::types::commit::Record::Other(val) = record {
-            println!("{:?}", val);
+            println!(
+                "{}: {}",
+                val.operation.collection(),
+                to_dag_json(&val.data)?
+            );
         }
     }
 
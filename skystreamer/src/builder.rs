@@ -0,0 +1,279 @@
+//! Connection options for [`crate::RepoSubscription`].
+use crate::{Error, RepoSubscription, Result};
+use atrium_api::com::atproto::sync::subscribe_repos::NSID;
+use std::time::Duration;
+use tokio_tungstenite::{
+    connect_async_with_config,
+    tungstenite::{
+        client::IntoClientRequest,
+        handshake::client::Request,
+        http::{header, HeaderName, HeaderValue},
+        protocol::WebSocketConfig,
+    },
+};
+
+/// The websocket scheme used to connect to a relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scheme {
+    /// Plain websocket, useful for local test relays
+    Ws,
+    /// Websocket over TLS
+    #[default]
+    Wss,
+}
+
+impl Scheme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scheme::Ws => "ws",
+            Scheme::Wss => "wss",
+        }
+    }
+}
+
+/// A builder for [`RepoSubscription`], for when the defaults of
+/// [`RepoSubscription::new`] are not enough.
+///
+/// # Example
+/// ```no_run
+/// use skystreamer::{builder::Scheme, RepoSubscription};
+/// use std::time::Duration;
+///
+/// let subscription = RepoSubscription::builder("localhost:2470")
+///     .scheme(Scheme::Ws)
+///     .timeout(Duration::from_secs(60))
+///     .user_agent("my-collector/1.0")
+///     .cursor(1234)
+///     .connect()
+///     .await
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RepoSubscriptionBuilder {
+    host: String,
+    scheme: Scheme,
+    endpoint: Option<String>,
+    timeout: Option<Duration>,
+    headers: Vec<(String, String)>,
+    user_agent: Option<String>,
+    max_frame_size: Option<usize>,
+    max_message_size: Option<usize>,
+    cursor: Option<i64>,
+}
+
+impl RepoSubscriptionBuilder {
+    /// Create a new builder connecting to the given relay host, e.g. `bsky.network`
+    pub fn new(host: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            scheme: Scheme::default(),
+            endpoint: None,
+            timeout: None,
+            headers: vec![],
+            user_agent: None,
+            max_frame_size: None,
+            max_message_size: None,
+            cursor: None,
+        }
+    }
+
+    /// Set the websocket scheme, defaults to [`Scheme::Wss`]
+    pub fn scheme(mut self, scheme: Scheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    /// Connect to a full endpoint URL instead of building one from the host and scheme.
+    ///
+    /// The cursor, if any, is appended to the URL as a query parameter.
+    pub fn endpoint(mut self, url: &str) -> Self {
+        self.endpoint = Some(url.to_string());
+        self
+    }
+
+    /// How long to wait for a message before considering the connection dead.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Add a custom header to the websocket handshake request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Set the `User-Agent` header of the websocket handshake request
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = Some(user_agent.to_string());
+        self
+    }
+
+    /// Set the maximum size of a single websocket frame
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Set the maximum size of a websocket message
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// Resume the firehose from the given `seq` cursor
+    pub fn cursor(mut self, cursor: i64) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    /// Build the endpoint URL, resuming from `cursor` if set
+    pub(crate) fn url(&self, cursor: Option<i64>) -> Result<url::Url> {
+        let mut url = match &self.endpoint {
+            Some(endpoint) => url::Url::parse(endpoint)?,
+            None => url::Url::parse(&format!(
+                "{}://{}/xrpc/{NSID}",
+                self.scheme.as_str(),
+                self.host
+            ))?,
+        };
+        if let Some(cursor) = cursor {
+            // Replace any cursor already in a custom endpoint, so reconnects don't repeat it
+            let pairs: Vec<(String, String)> = url
+                .query_pairs()
+                .filter(|(key, _)| key != "cursor")
+                .map(|(key, value)| (key.into_owned(), value.into_owned()))
+                .collect();
+            url.query_pairs_mut()
+                .clear()
+                .extend_pairs(pairs)
+                .append_pair("cursor", &cursor.to_string());
+        }
+        Ok(url)
+    }
+
+    /// Build the handshake request, resuming from `cursor` if set
+    pub(crate) fn request(&self, cursor: Option<i64>) -> Result<Request> {
+        let invalid_header = |e: &dyn std::fmt::Display| Error::Handshake(e.to_string());
+        let mut request = self.url(cursor)?.as_str().into_client_request()?;
+        let headers = request.headers_mut();
+        for (name, value) in &self.headers {
+            headers.append(
+                HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid_header(&e))?,
+                HeaderValue::from_str(value).map_err(|e| invalid_header(&e))?,
+            );
+        }
+        if let Some(user_agent) = &self.user_agent {
+            headers.insert(
+                header::USER_AGENT,
+                HeaderValue::from_str(user_agent).map_err(|e| invalid_header(&e))?,
+            );
+        }
+        Ok(request)
+    }
+
+    fn websocket_config(&self) -> WebSocketConfig {
+        let mut config = WebSocketConfig::default();
+        if let Some(size) = self.max_frame_size {
+            config.max_frame_size = Some(size);
+        }
+        if let Some(size) = self.max_message_size {
+            config.max_message_size = Some(size);
+        }
+        config
+    }
+
+    /// Open a websocket connection using these options, resuming from `cursor` if set
+    pub(crate) async fn open(
+        &self,
+        cursor: Option<i64>,
+    ) -> Result<
+        tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >,
+    > {
+        let request = self.request(cursor)?;
+        let (stream, res) =
+            connect_async_with_config(request, Some(self.websocket_config()), false).await?;
+        tracing::debug!("Connected to websocket: {:?}", res);
+        Ok(stream)
+    }
+
+    /// Connect to the relay and create the [`RepoSubscription`]
+    pub async fn connect(self) -> Result<RepoSubscription> {
+        let stream = self.open(self.cursor).await?;
+        Ok(RepoSubscription {
+            stream,
+            commit_cursor: self.cursor,
+            timeout: self.timeout,
+            timed_out: false,
+            recorder: None,
+            options: self,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subscribe_url_with_cursor() {
+        let builder = RepoSubscriptionBuilder::new("bsky.network");
+        assert_eq!(
+            builder.url(None).unwrap().as_str(),
+            "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos"
+        );
+        assert_eq!(
+            builder.url(Some(1234)).unwrap().as_str(),
+            "wss://bsky.network/xrpc/com.atproto.sync.subscribeRepos?cursor=1234"
+        );
+    }
+
+    #[test]
+    fn subscribe_url_options() {
+        let builder = RepoSubscriptionBuilder::new("localhost:2470").scheme(Scheme::Ws);
+        assert_eq!(
+            builder.url(None).unwrap().as_str(),
+            "ws://localhost:2470/xrpc/com.atproto.sync.subscribeRepos"
+        );
+
+        let builder = builder.endpoint("wss://relay.example.com/firehose?compress=false");
+        assert_eq!(
+            builder.url(Some(5)).unwrap().as_str(),
+            "wss://relay.example.com/firehose?compress=false&cursor=5"
+        );
+
+        let builder = builder.endpoint("wss://relay.example.com/firehose?cursor=1&compress=false");
+        assert_eq!(
+            builder.url(None).unwrap().as_str(),
+            "wss://relay.example.com/firehose?cursor=1&compress=false"
+        );
+        assert_eq!(
+            builder.url(Some(5)).unwrap().as_str(),
+            "wss://relay.example.com/firehose?compress=false&cursor=5"
+        );
+    }
+
+    #[test]
+    fn request_headers() {
+        let request = RepoSubscriptionBuilder::new("bsky.network")
+            .user_agent("skystreamer-test")
+            .header("x-api-key", "secret")
+            .request(None)
+            .unwrap();
+        assert_eq!(request.headers()[header::USER_AGENT], "skystreamer-test");
+        assert_eq!(request.headers()["x-api-key"], "secret");
+    }
+
+    #[test]
+    fn invalid_options() {
+        let builder = RepoSubscriptionBuilder::new("bsky.network").endpoint("not a url");
+        assert!(matches!(builder.url(None), Err(Error::Url(_))));
+
+        let builder = RepoSubscriptionBuilder::new("bsky.network").header("x-api-key", "a\nb");
+        assert!(matches!(builder.request(None), Err(Error::Handshake(_))));
+    }
+}
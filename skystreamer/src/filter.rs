@@ -0,0 +1,208 @@
+//! Filtering the firehose before records are decoded.
+//!
+//! A [`RecordFilter`] is checked against the repository of each commit and the path and
+//! action of each operation, which are known without reading the commit's blocks. Commits
+//! without any wanted operation are skipped entirely, so their CAR slice is never parsed,
+//! and unwanted operations of the remaining commits are never decoded.
+//!
+//! # Example
+//! ```no_run
+//! use skystreamer::{filter::RecordFilter, stream::EventStream, types::operation::Action, RepoSubscription};
+//!
+//! let filter = RecordFilter::new()
+//!     .collections(["app.bsky.feed.post", "app.bsky.graph.*"])
+//!     .actions([Action::Create]);
+//! let subscription = RepoSubscription::new("bsky.network").await?;
+//! let mut binding = EventStream::new(subscription).with_filter(filter);
+//! ```
+use crate::types::{commit::Commit, operation::Action};
+use atrium_api::types::string::Did;
+use std::collections::HashSet;
+
+/// Which records of the firehose to keep.
+///
+/// An empty filter keeps everything. Each condition that is set must hold for a
+/// record to be kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordFilter {
+    /// Exact collection NSIDs to keep
+    collections: HashSet<String>,
+    /// NSID prefixes to keep, from patterns like `app.bsky.feed.*`
+    prefixes: Vec<String>,
+    /// If set, only these repositories are kept
+    allow: Option<HashSet<String>>,
+    deny: HashSet<String>,
+    actions: Option<HashSet<Action>>,
+}
+
+impl RecordFilter {
+    /// Create a filter that keeps everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keep records of these collections.
+    ///
+    /// A collection ending in `.*` keeps every collection under that namespace,
+    /// e.g. `app.bsky.feed.*` keeps posts, likes and reposts.
+    pub fn collections<I, T>(mut self, collections: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        for collection in collections {
+            let collection = collection.into();
+            match collection.strip_suffix('*') {
+                Some(prefix) => self.prefixes.push(prefix.to_string()),
+                None => {
+                    self.collections.insert(collection);
+                }
+            }
+        }
+        self
+    }
+
+    /// Only keep records of these repositories
+    pub fn allow_dids<I, T>(mut self, dids: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.allow
+            .get_or_insert_with(HashSet::new)
+            .extend(dids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Drop records of these repositories, even if they are allowed
+    pub fn deny_dids<I, T>(mut self, dids: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.deny.extend(dids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only keep records changed by these actions
+    pub fn actions(mut self, actions: impl IntoIterator<Item = Action>) -> Self {
+        self.actions
+            .get_or_insert_with(HashSet::new)
+            .extend(actions);
+        self
+    }
+
+    /// Whether the filter keeps everything
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Whether records of the repository `did` may be kept
+    pub fn matches_repo(&self, did: &Did) -> bool {
+        let did = did.as_str();
+        !self.deny.contains(did) && self.allow.as_ref().is_none_or(|allow| allow.contains(did))
+    }
+
+    /// Whether a record of `collection`, changed by `action`, is kept
+    pub fn matches_op(&self, action: Action, collection: &str) -> bool {
+        if self
+            .actions
+            .as_ref()
+            .is_some_and(|actions| !actions.contains(&action))
+        {
+            return false;
+        }
+        if self.collections.is_empty() && self.prefixes.is_empty() {
+            return true;
+        }
+        self.collections.contains(collection)
+            || self
+                .prefixes
+                .iter()
+                .any(|prefix| collection.starts_with(prefix.as_str()))
+    }
+
+    /// Drop the operations of `commit` the filter does not keep.
+    ///
+    /// Returns `None` if nothing is left, in which case the commit need not be decoded at all.
+    pub fn apply(&self, mut commit: Commit) -> Option<Commit> {
+        if self.is_empty() {
+            return Some(commit);
+        }
+        if !self.matches_repo(&commit.inner().repo) {
+            return None;
+        }
+        commit
+            .operations
+            .retain(|op| self.matches_op(op.action(), op.collection()));
+        (!commit.operations.is_empty()).then_some(commit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CommitBuilder;
+
+    #[test]
+    fn filter_commits() {
+        let alice: Did = "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap();
+        let bob: Did = "did:plc:ewvi7nxzyoun6zhxrhs64oiz".parse().unwrap();
+        let commit = Commit::from(
+            CommitBuilder::new(alice.clone())
+                .post("3l6oveex3ii2l", "Hello, world!")
+                .follow("3l6oveex3ii2m", bob.clone())
+                .delete("app.bsky.feed.like", "3l6oveex3ii2n")
+                .build(),
+        );
+        let paths = |commit: Option<Commit>| {
+            commit.map(|commit| {
+                commit
+                    .operations
+                    .iter()
+                    .map(|op| op.collection().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(
+            paths(RecordFilter::new().apply(commit.clone()))
+                .unwrap()
+                .len(),
+            3
+        );
+        assert_eq!(
+            paths(
+                RecordFilter::new()
+                    .collections(["app.bsky.feed.*"])
+                    .apply(commit.clone())
+            ),
+            Some(vec![
+                "app.bsky.feed.post".to_string(),
+                "app.bsky.feed.like".to_string()
+            ])
+        );
+        assert_eq!(
+            paths(
+                RecordFilter::new()
+                    .collections(["app.bsky.feed.post", "app.bsky.graph.follow"])
+                    .actions([Action::Delete])
+                    .apply(commit.clone())
+            ),
+            None
+        );
+        assert_eq!(
+            paths(
+                RecordFilter::new()
+                    .allow_dids([bob.as_str()])
+                    .apply(commit.clone())
+            ),
+            None
+        );
+        assert!(RecordFilter::new()
+            .allow_dids([alice.as_str(), bob.as_str()])
+            .deny_dids([alice.as_str()])
+            .apply(commit)
+            .is_none());
+    }
+}
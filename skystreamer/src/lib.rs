@@ -1,69 +1,249 @@
 // pub mod config;
+pub mod builder;
+pub mod filter;
+pub mod reconnect;
+pub mod replay;
 pub mod stream;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
 pub mod types;
 pub mod util;
+pub mod verify;
 
 use std::convert::Infallible;
 pub const BLUESKY_FEED_DOMAIN: &str = "bsky.network";
 use crate::types::Frame;
 use atrium_api::{
     app::bsky::feed::Post as BPost,
-    com::atproto::sync::subscribe_repos::{Commit, NSID},
+    com::atproto::sync::subscribe_repos::Commit,
     types::{CidLink, Collection},
 };
+pub use builder::RepoSubscriptionBuilder;
 
 use futures::StreamExt;
 
 use ipld_core::ipld::Ipld;
 use tokio::net::TcpStream;
-use tokio_tungstenite::{
-    connect_async,
-    tungstenite::{client::IntoClientRequest, Message},
-    MaybeTlsStream, WebSocketStream,
-};
-use types::{operation::Operation, PostData, Subscription};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use types::{event::Event, operation::Operation, PostData, Subscription};
 // use types::{CommitHandler, PostData, Subscription};
 /// Error handling for the skystreamer crate
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
     #[error("Failed to connect to websocket: {0}")]
-    Connect(#[from] tokio_tungstenite::tungstenite::Error),
+    Connect(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("Invalid relay URL: {0}")]
+    Url(String),
+    #[error("Websocket handshake failed: {0}")]
+    Handshake(String),
+    #[error("No message received from the relay within {0:?}")]
+    Timeout(std::time::Duration),
+    #[error("Websocket protocol error: {0}")]
+    Protocol(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
     #[error("Failed to decide CBOR: {0}")]
     CborDecoder(#[from] serde_ipld_dagcbor::DecodeError<std::io::Error>),
     #[error("Failed to decode CBOR (How!?): {0}")]
     CborDecode(#[from] serde_ipld_dagcbor::DecodeError<Infallible>),
+    #[error("Failed to encode CBOR: {0}")]
+    CborEncode(#[from] serde_ipld_dagcbor::EncodeError<std::collections::TryReserveError>),
+    #[error("Failed to encode DAG-JSON: {0}")]
+    DagJsonEncode(#[from] serde_ipld_dagjson::EncodeError),
+    #[error("Failed to decode DAG-JSON: {0}")]
+    DagJsonDecode(#[from] serde_ipld_dagjson::DecodeError),
+    #[error("Failed to convert JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to deserialize IPLD: {0}")]
+    IpldDecode(#[from] ipld_core::serde::SerdeError),
     #[error("Failed to decode CAR data: {0}")]
     CarDecoder(#[from] rs_car::CarDecodeError),
+    #[error("Invalid CID in CAR data: {0}")]
+    InvalidCid(#[from] cid::Error),
+    #[error("Block {cid} failed integrity check: {reason}")]
+    BlockIntegrity { cid: cid::Cid, reason: String },
     #[error("Could not find item with operation cid {0:?} out of {1} items")]
     ItemNotFound(Option<CidLink>, usize),
     #[error("Invalid frame data: {0:?}")]
     InvalidFrameData(Vec<u8>),
     #[error("Invalid frame type: {0:?}")]
     InvalidFrameType(Ipld),
+    #[error("Invalid AT-URI: {0}")]
+    InvalidAtUri(String),
+    #[error("Media has no blob to download")]
+    MissingBlob,
     #[error("ATrium error: {0}")]
     AtriumError(String),
+    #[error("Custom record decoder failed: {0}")]
+    CustomDecoder(Box<dyn std::error::Error + Send + Sync>),
+    #[error("Requested cursor is ahead of the relay: {0:?}")]
+    FutureCursor(Option<String>),
+    #[error("Consumer is too slow, the relay closed the connection: {0:?}")]
+    ConsumerTooSlow(Option<String>),
+    #[error("Relay sent error {error}: {message:?}")]
+    RelayError {
+        error: String,
+        message: Option<String>,
+    },
+    #[error("Commit verification failed: {0}")]
+    Verification(Box<verify::VerificationError>),
+    #[error("Failed to decode commit {seq} of {}: {source}", repo.as_str())]
+    Commit {
+        repo: atrium_api::types::string::Did,
+        seq: i64,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("Failed to decode record {path} ({cid:?}) of {}: {source}", repo.as_str())]
+    Record {
+        repo: atrium_api::types::string::Did,
+        path: String,
+        cid: Option<String>,
+        #[source]
+        source: Box<Error>,
+    },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> Self {
+        use tokio_tungstenite::tungstenite::Error as WsError;
+        match value {
+            WsError::Url(e) => Error::Url(e.to_string()),
+            WsError::Http(response) => {
+                Error::Handshake(format!("relay responded with HTTP {}", response.status()))
+            }
+            WsError::HttpFormat(e) => Error::Handshake(e.to_string()),
+            WsError::Protocol(e) => Error::Protocol(e.to_string()),
+            e => Error::Connect(Box::new(e)),
+        }
+    }
 }
 
-pub(crate) type Result<T> = std::result::Result<T, Error>;
+impl From<url::ParseError> for Error {
+    fn from(value: url::ParseError) -> Self {
+        Error::Url(value.to_string())
+    }
+}
+
+/// A result with the skystreamer [`Error`] type
+pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct RepoSubscription {
     stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
-    _commit_cursor: u64,
+    /// Connection options, kept around for reconnecting
+    options: RepoSubscriptionBuilder,
+    /// The last `seq` delivered by the stream, or the cursor we started from
+    commit_cursor: Option<i64>,
     timeout: Option<tokio::time::Duration>,
+    /// Whether the last read timed out, leaving the connection unusable until reconnected
+    timed_out: bool,
+    /// Where to record the raw frames received from the relay, if anywhere
+    recorder: Option<replay::RecorderTask>,
 }
 
 impl RepoSubscription {
-    pub async fn new(bgs: &str) -> std::result::Result<Self, Box<dyn std::error::Error>> {
-        // todo: somehow get the websocket to update the damn params
-        let request = format!("wss://{bgs}/xrpc/{NSID}").into_client_request()?;
-        // request.
-        let (stream, res) = connect_async(request).await?;
-        tracing::debug!("Connected to websocket: {:?}", res);
-        Ok(RepoSubscription {
-            stream,
-            _commit_cursor: 0,
-            timeout: None,
-        })
+    pub async fn new(bgs: &str) -> Result<Self> {
+        Self::builder(bgs).connect().await
+    }
+
+    /// Connect to a relay and resume the firehose from the given `seq` cursor.
+    ///
+    /// The relay will replay every event after `cursor` that is still within its
+    /// backfill window, then continue with live events.
+    pub async fn with_cursor(bgs: &str, cursor: i64) -> Result<Self> {
+        Self::builder(bgs).cursor(cursor).connect().await
+    }
+
+    /// Create a [`RepoSubscriptionBuilder`] to customize the connection to a relay.
+    pub fn builder(bgs: &str) -> RepoSubscriptionBuilder {
+        RepoSubscriptionBuilder::new(bgs)
+    }
+
+    /// Get the `seq` of the last commit delivered by this subscription.
+    ///
+    /// If nothing has been delivered yet, this is the cursor the subscription was
+    /// started with, if any. Persist this value and pass it to
+    /// [`RepoSubscription::with_cursor`] to resume after a restart.
+    pub fn cursor(&self) -> Option<i64> {
+        self.commit_cursor
+    }
+
+    /// Re-establish the websocket connection, resuming from [`RepoSubscription::cursor`].
+    pub async fn reconnect(&mut self) -> Result<()> {
+        self.stream = self.options.open(self.commit_cursor).await?;
+        self.timed_out = false;
+        Ok(())
+    }
+
+    /// Record every raw frame received from the relay with `recorder`,
+    /// so it can be replayed later with a [`replay::FileSubscription`].
+    ///
+    /// Frames are written on a separate thread. If it falls behind, receiving the next
+    /// frame waits until it catches up.
+    pub fn with_recorder(mut self, recorder: replay::Recorder) -> Self {
+        self.recorder = Some(replay::RecorderTask::spawn(recorder));
+        self
+    }
+
+    /// Stop recording, waiting until every frame received so far has been written.
+    ///
+    /// Dropping the subscription also stops recording, but without waiting for the
+    /// remaining frames to reach the archive.
+    pub async fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish().await;
+        }
+    }
+
+    /// Wait for the next event on the stream.
+    ///
+    /// Returns [`Error::Timeout`] if no message arrived within the timeout, after which
+    /// the connection is considered dead, and `None` once the connection is closed.
+    pub(crate) async fn next_event(&mut self) -> Option<Result<Event>> {
+        let event = next_event(self).await;
+        if let Some(Ok(event)) = &event {
+            if let Some(seq) = event.seq() {
+                self.commit_cursor = Some(seq);
+            }
+        }
+        event
+    }
+
+    /// Read the next binary frame from the websocket, recording it if a recorder is set
+    async fn read_frame(&mut self) -> Option<Result<Frame>> {
+        loop {
+            match self.stream.next().await? {
+                Ok(Message::Binary(data)) => {
+                    if let Some(recorder) = &self.recorder {
+                        recorder.record(&data).await;
+                    }
+                    return Some(Frame::try_from(data.as_slice()));
+                }
+                Ok(Message::Text(text)) => {
+                    return Some(Err(Error::InvalidFrameData(text.into_bytes())))
+                }
+                Ok(Message::Close(frame)) => {
+                    tracing::debug!(?frame, "Websocket closed by relay");
+                    return None;
+                }
+                // Pings are answered by tungstenite itself
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => continue,
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+
+    /// Wait for the next commit on the stream, skipping any other events.
+    ///
+    /// Returns `None` once the connection is closed.
+    pub(crate) async fn next_commit(&mut self) -> Option<Result<Commit>> {
+        loop {
+            match self.next_event().await? {
+                Ok(Event::Commit(commit)) => return Some(Ok(commit.into_inner())),
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
     }
 
     // pub async fn run(
@@ -91,46 +271,136 @@ impl RepoSubscription {
     //     Ok(())
     // }
 
-    pub async fn stream_commits(
-        &mut self,
-    ) -> impl futures::Stream<Item = std::result::Result<Commit, Box<dyn std::error::Error>>> + '_
-    {
+    pub async fn stream_commits(&mut self) -> impl futures::Stream<Item = Result<Commit>> + '_ {
         let a = self.stream.get_config();
         tracing::debug!("Stream config: {:?}", a);
         futures::stream::unfold(self, |this| async move {
-            loop {
-                let timeout_duration = this
-                    .timeout
-                    .unwrap_or_else(|| tokio::time::Duration::from_secs(30));
-
-                match tokio::time::timeout(timeout_duration, this.next()).await {
-                    Ok(Some(Ok(Frame::Message(Some(t), message)))) if t.as_str() == "#commit" => {
-                        // tracing::trace!("Received commit message: {:?}", message);
-                        let commit = serde_ipld_dagcbor::from_reader(message.body.as_slice());
-                        // tracing::trace!("Decoded commit: {:?}", commit);
-                        return Some((commit.map_err(|e| e.into()), this));
-                    }
-                    Ok(Some(m)) => {
-                        tracing::trace!("Unexpected message: {:?}", m);
-                        continue;
-                    }
-                    Ok(None) => return None,
-                    Err(elapsed) => {
-                        tracing::warn!(?elapsed, "Timeout waiting for next message");
-                        return None;
-                    }
-                }
-            }
+            let commit = this.next_commit().await?;
+            Some((commit, this))
+        })
+    }
+
+    /// Stream every event from the firehose, including identity, account and info messages.
+    pub async fn stream_events(&mut self) -> impl futures::Stream<Item = Result<Event>> + '_ {
+        futures::stream::unfold(self, |this| async move {
+            let event = this.next_event().await?;
+            Some((event, this))
         })
     }
 }
 
 impl Subscription for RepoSubscription {
+    /// Wait for the next frame from the relay.
+    ///
+    /// Returns [`Error::Timeout`] if no frame arrived within the timeout, after which
+    /// the connection is considered dead and `None` is returned until it is resumed.
     async fn next(&mut self) -> Option<Result<Frame>> {
-        if let Some(Ok(Message::Binary(data))) = self.stream.next().await {
-            Some(Frame::try_from(data.as_slice()))
-        } else {
-            None
+        if self.timed_out {
+            return None;
+        }
+        let timeout_duration = self
+            .timeout
+            .unwrap_or_else(|| tokio::time::Duration::from_secs(30));
+        match tokio::time::timeout(timeout_duration, self.read_frame()).await {
+            Ok(frame) => frame,
+            Err(elapsed) => {
+                tracing::warn!(?elapsed, "Timeout waiting for next message");
+                self.timed_out = true;
+                Some(Err(Error::Timeout(timeout_duration)))
+            }
+        }
+    }
+
+    async fn resume(&mut self, cursor: Option<i64>) -> Result<()> {
+        if cursor.is_some() {
+            self.commit_cursor = cursor;
+        }
+        self.reconnect().await
+    }
+}
+
+/// A message read from a subscription, with the body of commits possibly left to decode later
+pub(crate) enum FirehoseMessage {
+    Event(Event),
+    /// A `#commit` message, of which only the `seq` has been read
+    Commit {
+        seq: i64,
+        body: Vec<u8>,
+    },
+}
+
+impl FirehoseMessage {
+    /// The sequence number of the message, if it has one
+    pub(crate) fn seq(&self) -> Option<i64> {
+        match self {
+            FirehoseMessage::Event(event) => event.seq(),
+            FirehoseMessage::Commit { seq, .. } => Some(*seq),
+        }
+    }
+
+    /// Decode the message into an event, decoding the body of a commit if needed
+    pub(crate) fn into_event(self) -> Result<Event> {
+        match self {
+            FirehoseMessage::Event(event) => Ok(event),
+            FirehoseMessage::Commit { body, .. } => {
+                Ok(Event::Commit(Box::new(Event::commit_from_body(&body)?)))
+            }
+        }
+    }
+}
+
+/// Wait for the next event of a subscription, skipping frames that are not events.
+///
+/// Returns `None` once the subscription has ended.
+pub(crate) async fn next_event<S: Subscription>(subscription: &mut S) -> Option<Result<Event>> {
+    let message = next_message(subscription, false).await?;
+    Some(message.and_then(FirehoseMessage::into_event))
+}
+
+/// Wait for the next message of a subscription, skipping frames that are not events.
+///
+/// If `defer_commits` is set, only the `seq` of `#commit` messages is decoded, so the
+/// rest of the work can be moved off the task reading the subscription.
+/// Returns `None` once the subscription has ended.
+pub(crate) async fn next_message<S: Subscription>(
+    subscription: &mut S,
+    defer_commits: bool,
+) -> Option<Result<FirehoseMessage>> {
+    loop {
+        match subscription.next().await? {
+            Ok(Frame::Message(Some(t), message)) if defer_commits && t == "#commit" => {
+                // A body without a readable `seq` is decoded in full, to report why it is invalid
+                return Some(match Event::commit_seq(&message.body) {
+                    Ok(seq) => Ok(FirehoseMessage::Commit {
+                        seq,
+                        body: message.body,
+                    }),
+                    Err(_) => Event::commit_from_body(&message.body)
+                        .map(|commit| FirehoseMessage::Event(Event::Commit(Box::new(commit)))),
+                });
+            }
+            Ok(Frame::Message(Some(t), message)) => match Event::from_message(&t, &message.body) {
+                Ok(Some(event)) => {
+                    if let Event::Info(info) = &event {
+                        tracing::warn!(?info, "Received info message from relay");
+                    }
+                    return Some(Ok(FirehoseMessage::Event(event)));
+                }
+                Ok(None) => {
+                    tracing::trace!("Unknown message type: {}", t);
+                    continue;
+                }
+                Err(e) => return Some(Err(e)),
+            },
+            Ok(Frame::Error(frame)) => {
+                tracing::error!(?frame, "Received error frame from relay");
+                return Some(Err(Error::from(frame)));
+            }
+            Ok(Frame::Message(None, _)) => {
+                tracing::trace!("Received message without a type");
+                continue;
+            }
+            Err(e) => return Some(Err(e)),
         }
     }
 }
@@ -158,7 +428,8 @@ pub async fn handle_commit(commit: &Commit) -> Result<Vec<PostData>> {
 
         let record = types::commit::extract_post_record(op, &commit.blocks).await?;
         // posts.push(record.data);
-        let post_data = PostData::new(commit.repo.clone(), commit.commit.clone(), record);
+        let rkey = op.path.split_once('/').map_or("", |(_, rkey)| rkey);
+        let post_data = PostData::new(commit.repo.clone(), commit.commit.clone(), record, rkey);
         posts.push(post_data);
     }
 
@@ -0,0 +1,195 @@
+//! Reconnection policies for long-running firehose streams.
+//!
+//! By default, a [`crate::stream::EventStream`] ends as soon as the underlying websocket
+//! drops or times out. Attaching a [`ReconnectPolicy`] makes the stream reconnect
+//! from the last seen `seq` instead, waiting an exponentially increasing (and jittered)
+//! delay between attempts.
+use rand::Rng;
+use std::{sync::Arc, time::Duration};
+
+/// Information about a reconnection attempt, passed to [`ReconnectPolicy::on_reconnect`] callbacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReconnectEvent {
+    /// The attempt number, starting at 1 for the first attempt after a disconnect
+    pub attempt: u32,
+    /// How long the stream will wait before attempting to reconnect
+    pub delay: Duration,
+    /// The cursor the stream will resume from, if any
+    pub cursor: Option<i64>,
+}
+
+type ReconnectCallback = Arc<dyn Fn(&ReconnectEvent) + Send + Sync>;
+
+/// An exponential backoff policy for reconnecting to the firehose.
+///
+/// The delay before attempt `n` (starting at 0) is `initial_delay * multiplier^n`,
+/// capped at `max_delay`, then reduced by a random fraction of up to `jitter`.
+///
+/// # Example
+/// ```no_run
+/// use skystreamer::{reconnect::ReconnectPolicy, stream::EventStream, RepoSubscription};
+/// use std::time::Duration;
+///
+/// let subscription = RepoSubscription::new("bsky.network").await.unwrap();
+/// let policy = ReconnectPolicy::default()
+///     .max_delay(Duration::from_secs(30))
+///     .on_reconnect(|event| eprintln!("reconnecting: {:?}", event));
+/// let mut binding = EventStream::new(subscription).with_reconnect(policy);
+/// ```
+#[derive(Clone)]
+pub struct ReconnectPolicy {
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_retries: Option<u32>,
+    callback: Option<ReconnectCallback>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(60),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_retries: None,
+            callback: None,
+        }
+    }
+}
+
+impl std::fmt::Debug for ReconnectPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReconnectPolicy")
+            .field("initial_delay", &self.initial_delay)
+            .field("max_delay", &self.max_delay)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("max_retries", &self.max_retries)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl ReconnectPolicy {
+    /// Create a new policy with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the delay before the first reconnection attempt
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Set the upper bound for the delay between attempts
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Set the factor the delay grows by after each failed attempt
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Set the maximum fraction of the delay that is randomly subtracted, between `0.0` and `1.0`
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Give up after this many consecutive failed attempts.
+    ///
+    /// By default, the stream retries forever.
+    pub fn max_retries(mut self, retries: u32) -> Self {
+        self.max_retries = Some(retries);
+        self
+    }
+
+    /// Register a callback that is called before every reconnection attempt
+    pub fn on_reconnect<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(&ReconnectEvent) + Send + Sync + 'static,
+    {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// Whether another attempt is allowed after `attempts` consecutive failures
+    pub(crate) fn should_retry(&self, attempts: u32) -> bool {
+        self.max_retries.is_none_or(|max| attempts < max)
+    }
+
+    /// Compute the delay before the given (zero-based) attempt, without jitter
+    pub(crate) fn base_delay(&self, attempt: u32) -> Duration {
+        let factor = self.multiplier.powi(attempt.min(i32::MAX as u32) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+
+    /// Compute the jittered delay before the given (zero-based) attempt
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let base = self.base_delay(attempt);
+        if self.jitter == 0.0 {
+            return base;
+        }
+        let reduction = rand::thread_rng().gen_range(0.0..=self.jitter);
+        base.mul_f64(1.0 - reduction)
+    }
+
+    /// Report a reconnection attempt to tracing and the registered callback, if any
+    pub(crate) fn notify(&self, event: &ReconnectEvent) {
+        tracing::warn!(
+            attempt = event.attempt,
+            delay = ?event.delay,
+            cursor = ?event.cursor,
+            "Firehose connection lost, reconnecting"
+        );
+        if let Some(callback) = &self.callback {
+            callback(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_caps() {
+        let policy = ReconnectPolicy::new()
+            .initial_delay(Duration::from_secs(1))
+            .max_delay(Duration::from_secs(10))
+            .multiplier(2.0);
+        assert_eq!(policy.base_delay(0), Duration::from_secs(1));
+        assert_eq!(policy.base_delay(1), Duration::from_secs(2));
+        assert_eq!(policy.base_delay(3), Duration::from_secs(8));
+        assert_eq!(policy.base_delay(4), Duration::from_secs(10));
+        assert_eq!(policy.base_delay(100), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_stays_within_bounds() {
+        let policy = ReconnectPolicy::new()
+            .initial_delay(Duration::from_secs(4))
+            .jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.delay(0);
+            assert!(delay <= Duration::from_secs(4));
+            assert!(delay >= Duration::from_secs(2));
+        }
+    }
+
+    #[test]
+    fn max_retries() {
+        let policy = ReconnectPolicy::new().max_retries(2);
+        assert!(policy.should_retry(0));
+        assert!(policy.should_retry(1));
+        assert!(!policy.should_retry(2));
+        assert!(ReconnectPolicy::new().should_retry(u32::MAX));
+    }
+}
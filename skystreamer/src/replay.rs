@@ -0,0 +1,472 @@
+//! Recording and replaying raw firehose frames.
+//!
+//! A [`Recorder`] appends every binary frame a [`crate::RepoSubscription`] receives to an
+//! archive file, and a [`FileSubscription`] replays such an archive through the
+//! [`Subscription`] trait. Wrapping a replay in a [`crate::stream::EventStream`] reproduces
+//! exactly what the stream saw, without network access.
+//!
+//! # Archive format
+//!
+//! An archive starts with the 8 byte magic `SKYFRM01`, followed by any number of entries:
+//!
+//! | Size         | Content                                                      |
+//! |--------------|--------------------------------------------------------------|
+//! | 8 bytes      | Receive time, in microseconds since the Unix epoch (`i64` BE) |
+//! | 4 bytes      | Length of the frame in bytes (`u32` BE)                      |
+//! | length bytes | The raw frame, as received from the websocket                |
+//!
+//! # Example
+//! ```no_run
+//! use skystreamer::{replay::{FileSubscription, Recorder}, stream::EventStream, RepoSubscription};
+//!
+//! // Record the firehose...
+//! let subscription = RepoSubscription::new("bsky.network")
+//!     .await?
+//!     .with_recorder(Recorder::create("firehose.frames")?);
+//!
+//! // ...and replay it later, at the speed it was received
+//! let replay = FileSubscription::open("firehose.frames").await?.realtime(true);
+//! let mut binding = EventStream::new(replay);
+//! ```
+use crate::{
+    types::{Frame, Subscription},
+    Error, Result,
+};
+use chrono::{DateTime, Utc};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    time::{Duration, Instant},
+};
+
+/// The magic bytes at the start of every archive
+const MAGIC: &[u8; 8] = b"SKYFRM01";
+
+/// The size of the header of every entry: receive time and frame length
+const ENTRY_HEADER: u64 = 12;
+
+/// The largest frame replayed by default, the default maximum websocket message size
+const DEFAULT_MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// Appends raw frames, with the time they were received, to an archive.
+pub struct Recorder {
+    writer: Box<dyn Write + Send>,
+}
+
+impl Recorder {
+    /// Create a new archive at `path`, replacing any existing file
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?))
+    }
+
+    /// Append to the archive at `path`, creating it if it does not exist.
+    ///
+    /// Fails with an [`io::ErrorKind::InvalidData`] error if the file exists, but is
+    /// not an archive. A partially written entry at the end of the archive, e.g. from a
+    /// crash while recording, is truncated before appending.
+    pub fn append(path: impl AsRef<Path>) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+        if file.metadata()?.len() > 0 {
+            let mut magic = [0; MAGIC.len()];
+            match file.read_exact(&mut magic) {
+                Ok(()) if &magic == MAGIC => {}
+                Ok(()) | Err(_) => {
+                    return Err(
+                        io::Error::new(io::ErrorKind::InvalidData, "not a frame archive").into(),
+                    )
+                }
+            }
+            let len = file.metadata()?.len();
+            let complete = complete_len(&mut file, len)?;
+            if complete < len {
+                tracing::warn!(
+                    truncated = len - complete,
+                    "Truncating a partially written entry at the end of the archive"
+                );
+                file.set_len(complete)?;
+            }
+            return Ok(Self {
+                writer: Box::new(BufWriter::new(file)),
+            });
+        }
+        Self::new(BufWriter::new(file))
+    }
+
+    /// Start a new archive in `writer`
+    pub fn new(writer: impl Write + Send + 'static) -> Result<Self> {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+        writer.write_all(MAGIC)?;
+        Ok(Self { writer })
+    }
+
+    /// Record a frame received just now
+    pub fn record(&mut self, frame: &[u8]) -> Result<()> {
+        self.record_at(Utc::now(), frame)
+    }
+
+    /// Record a frame received at `time`
+    pub fn record_at(&mut self, time: DateTime<Utc>, frame: &[u8]) -> Result<()> {
+        let len = u32::try_from(frame.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+        self.writer
+            .write_all(&time.timestamp_micros().to_be_bytes())?;
+        self.writer.write_all(&len.to_be_bytes())?;
+        self.writer.write_all(frame)?;
+        Ok(())
+    }
+
+    /// Write any buffered frames to the archive
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// The length of the archive `file` of `len` bytes, up to the end of its last complete entry
+fn complete_len(file: &mut File, len: u64) -> io::Result<u64> {
+    let mut end = MAGIC.len() as u64;
+    let mut header = [0; ENTRY_HEADER as usize];
+    while end + ENTRY_HEADER <= len {
+        file.seek(SeekFrom::Start(end))?;
+        file.read_exact(&mut header)?;
+        let frame_len = u32::from_be_bytes(header[8..].try_into().unwrap());
+        let next = end + ENTRY_HEADER + u64::from(frame_len);
+        if next > len {
+            break;
+        }
+        end = next;
+    }
+    Ok(end)
+}
+
+/// How many frames can be waiting for a [`RecorderTask`] before receiving more waits
+const RECORD_BUFFER: usize = 1024;
+
+/// Runs a [`Recorder`] on its own thread, so blocking writes don't stall the async task
+/// receiving frames.
+///
+/// Dropping the task closes it without waiting, the thread still writes the frames
+/// queued so far before exiting. Use [`RecorderTask::finish`] to wait for them.
+pub(crate) struct RecorderTask {
+    frames: tokio::sync::mpsc::Sender<(DateTime<Utc>, Vec<u8>)>,
+    /// Resolved once the thread has written every frame and exited
+    done: tokio::sync::oneshot::Receiver<()>,
+}
+
+impl RecorderTask {
+    /// Start writing frames sent to the task with `recorder`
+    pub(crate) fn spawn(mut recorder: Recorder) -> Self {
+        let (frames, mut receiver) =
+            tokio::sync::mpsc::channel::<(DateTime<Utc>, Vec<u8>)>(RECORD_BUFFER);
+        let (finished, done) = tokio::sync::oneshot::channel();
+        std::thread::spawn(move || {
+            while let Some((time, frame)) = receiver.blocking_recv() {
+                if let Err(e) = recorder.record_at(time, &frame) {
+                    tracing::error!("Failed to record frame: {}", e);
+                }
+            }
+            if let Err(e) = recorder.flush() {
+                tracing::error!("Failed to flush recorded frames: {}", e);
+            }
+            let _ = finished.send(());
+        });
+        Self { frames, done }
+    }
+
+    /// Record a frame received just now, waiting if the writer has fallen behind
+    pub(crate) async fn record(&self, frame: &[u8]) {
+        if self
+            .frames
+            .send((Utc::now(), frame.to_vec()))
+            .await
+            .is_err()
+        {
+            tracing::error!("Failed to record frame: the recorder thread has stopped");
+        }
+    }
+
+    /// Stop the task, waiting until every frame sent so far has been written
+    pub(crate) async fn finish(self) {
+        drop(self.frames);
+        if self.done.await.is_err() {
+            tracing::error!("The recorder thread stopped before writing every frame");
+        }
+    }
+}
+
+/// A [`Subscription`] replaying the frames of an archive written by a [`Recorder`].
+///
+/// By default, frames are replayed as fast as they are read. Replays end at the end
+/// of the archive and cannot be resumed.
+pub struct FileSubscription<R = BufReader<tokio::fs::File>> {
+    reader: R,
+    realtime: bool,
+    /// The receive time of the first frame, and when it was replayed
+    started: Option<(i64, Instant)>,
+    max_frame_size: usize,
+}
+
+impl FileSubscription {
+    /// Open the archive at `path`
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = tokio::fs::File::open(path).await?;
+        Self::new(BufReader::new(file)).await
+    }
+}
+
+impl<R: AsyncRead + Unpin> FileSubscription<R> {
+    /// Replay the archive read from `reader`
+    pub async fn new(mut reader: R) -> Result<Self> {
+        let mut magic = [0; MAGIC.len()];
+        reader.read_exact(&mut magic).await?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a frame archive").into());
+        }
+        Ok(Self {
+            reader,
+            realtime: false,
+            started: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        })
+    }
+
+    /// Whether to replay frames at the speed they were originally received
+    pub fn realtime(mut self, enabled: bool) -> Self {
+        self.realtime = enabled;
+        self
+    }
+
+    /// Set the largest frame to replay, 64 MiB by default.
+    ///
+    /// A larger entry fails with an [`io::ErrorKind::InvalidData`] error instead of being
+    /// read, so a corrupt length cannot cause a huge allocation.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Read the next raw frame and the time it was received, or `None` at the end of the archive.
+    pub async fn next_entry(&mut self) -> Option<Result<(DateTime<Utc>, Vec<u8>)>> {
+        let mut header = [0; ENTRY_HEADER as usize];
+        match read_entry_header(&mut self.reader, &mut header).await {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => return Some(Err(e.into())),
+        }
+        let micros = i64::from_be_bytes(header[..8].try_into().unwrap());
+        let len = u32::from_be_bytes(header[8..].try_into().unwrap()) as usize;
+        if len > self.max_frame_size {
+            return Some(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "frame of {len} bytes is larger than the maximum of {}",
+                    self.max_frame_size
+                ),
+            )
+            .into()));
+        }
+        let mut frame = vec![0; len];
+        if let Err(e) = self.reader.read_exact(&mut frame).await {
+            return Some(Err(e.into()));
+        }
+
+        if self.realtime {
+            let (first, start) = *self.started.get_or_insert((micros, Instant::now()));
+            let offset = Duration::from_micros(micros.saturating_sub(first).max(0) as u64);
+            tokio::time::sleep_until(start + offset).await;
+        }
+
+        let time = DateTime::from_timestamp_micros(micros).unwrap_or_default();
+        Some(Ok((time, frame)))
+    }
+}
+
+/// Fill `header`, returning `false` if the reader is at the end of the archive
+async fn read_entry_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    header: &mut [u8],
+) -> io::Result<bool> {
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..]).await? {
+            0 if read == 0 => return Ok(false),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => read += n,
+        }
+    }
+    Ok(true)
+}
+
+impl<R: AsyncRead + Unpin> Subscription for FileSubscription<R> {
+    async fn next(&mut self) -> Option<Result<Frame>> {
+        let entry = self.next_entry().await?;
+        Some(entry.and_then(|(_, frame)| Frame::try_from(frame.as_slice())))
+    }
+
+    async fn resume(&mut self, _cursor: Option<i64>) -> Result<()> {
+        Err(Error::Io(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "a replayed archive cannot be resumed",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{stream::EventStream, types::event::Event};
+    use futures::StreamExt;
+    use ipld_core::ipld::Ipld;
+
+    fn identity_frame(seq: i64) -> Vec<u8> {
+        let header = Ipld::Map(
+            [
+                ("op".to_string(), Ipld::Integer(1)),
+                ("t".to_string(), Ipld::String("#identity".to_string())),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let body = Ipld::Map(
+            [
+                ("seq".to_string(), Ipld::Integer(seq.into())),
+                (
+                    "did".to_string(),
+                    Ipld::String("did:plc:z72i7hdynmk6r22z27h6tvur".to_string()),
+                ),
+                (
+                    "time".to_string(),
+                    Ipld::String("2024-10-17T12:00:00.000Z".to_string()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        [
+            serde_ipld_dagcbor::to_vec(&header).unwrap(),
+            serde_ipld_dagcbor::to_vec(&body).unwrap(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!(
+            "skystreamer-record-and-replay-{}.frames",
+            std::process::id()
+        ));
+        let time = DateTime::from_timestamp_micros(1_729_166_400_000_000).unwrap();
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record_at(time, &identity_frame(1)).unwrap();
+        drop(recorder);
+        // Appending keeps the existing frames and does not repeat the magic
+        let recorder = RecorderTask::spawn(Recorder::append(&path).unwrap());
+        futures::executor::block_on(recorder.record(&identity_frame(2)));
+        futures::executor::block_on(recorder.finish());
+        let archive = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut replay =
+            futures::executor::block_on(FileSubscription::new(archive.as_slice())).unwrap();
+        let (recorded_at, frame) = futures::executor::block_on(replay.next_entry())
+            .unwrap()
+            .unwrap();
+        assert_eq!(recorded_at, time);
+        assert_eq!(frame, identity_frame(1));
+
+        let replay =
+            futures::executor::block_on(FileSubscription::new(archive.as_slice())).unwrap();
+        let mut binding = EventStream::new(replay);
+        let seqs = futures::executor::block_on(async {
+            binding
+                .stream_events()
+                .await
+                .unwrap()
+                .map(|event| event.seq())
+                .collect::<Vec<_>>()
+                .await
+        });
+        assert_eq!(seqs, vec![Some(1), Some(2)]);
+
+        // A truncated archive ends with an error
+        let mut replay =
+            futures::executor::block_on(FileSubscription::new(&archive[..archive.len() - 1]))
+                .unwrap();
+        let events = futures::executor::block_on(async {
+            let mut events = vec![];
+            while let Some(event) = crate::next_event(&mut replay).await {
+                events.push(event);
+            }
+            events
+        });
+        assert!(matches!(events[0], Ok(Event::Identity(_))));
+        assert!(matches!(events[1], Err(Error::Io(_))));
+    }
+
+    #[test]
+    fn append_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!(
+            "skystreamer-append-rejects-other-files-{}.frames",
+            std::process::id()
+        ));
+        std::fs::write(&path, b"not an archive").unwrap();
+        let result = Recorder::append(&path);
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData));
+        assert_eq!(contents, b"not an archive");
+    }
+
+    #[test]
+    fn append_truncates_partial_entries() {
+        let path = std::env::temp_dir().join(format!(
+            "skystreamer-append-truncates-partial-entries-{}.frames",
+            std::process::id()
+        ));
+        let mut recorder = Recorder::create(&path).unwrap();
+        recorder.record(&identity_frame(1)).unwrap();
+        recorder.record(&identity_frame(2)).unwrap();
+        drop(recorder);
+        // Cut the last entry short, as a crash while writing it would
+        let archive = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &archive[..archive.len() - 3]).unwrap();
+
+        let mut recorder = Recorder::append(&path).unwrap();
+        recorder.record(&identity_frame(3)).unwrap();
+        drop(recorder);
+        let archive = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut replay =
+            futures::executor::block_on(FileSubscription::new(archive.as_slice())).unwrap();
+        let frames = futures::executor::block_on(async {
+            let mut frames = vec![];
+            while let Some(entry) = replay.next_entry().await {
+                frames.push(entry.unwrap().1);
+            }
+            frames
+        });
+        assert_eq!(frames, vec![identity_frame(1), identity_frame(3)]);
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let mut archive = MAGIC.to_vec();
+        archive.extend_from_slice(&0i64.to_be_bytes());
+        archive.extend_from_slice(&u32::MAX.to_be_bytes());
+
+        let mut replay = futures::executor::block_on(FileSubscription::new(archive.as_slice()))
+            .unwrap()
+            .max_frame_size(1024);
+        let entry = futures::executor::block_on(replay.next_entry()).unwrap();
+        assert!(matches!(entry, Err(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData));
+    }
+}
@@ -3,9 +3,13 @@
 //! This module provides types, enums and functions for exporting data from the firehose.
 //!
 //!
-use crate::types::{commit, Post};
-use crate::Result;
-use futures::StreamExt;
+use crate::filter::RecordFilter;
+use crate::reconnect::{ReconnectEvent, ReconnectPolicy};
+use crate::types::{commit, custom::DecoderRegistry, event::Event, Post, Subscription};
+use crate::verify::CommitVerifier;
+use crate::{FirehoseMessage, Result};
+use futures::{future::Either, FutureExt, StreamExt};
+use std::sync::Arc;
 
 #[deprecated(
     note = "Please use [`skystreamer::stream::EventStream`] instead as it provides a more generic interface.",
@@ -51,6 +55,45 @@ impl PostStream {
     }
 }
 
+/// Whether the relay rejected the subscription in a way that reconnecting
+/// from the same cursor would only fail again.
+fn is_fatal(message: &Result<FirehoseMessage>) -> bool {
+    matches!(message, Err(crate::Error::FutureCursor(_)))
+}
+
+/// The records of a commit, with the commit's metadata
+type DecodedCommit = (Arc<commit::CommitMeta>, Vec<Result<commit::Record>>);
+
+/// Everything needed to decode a commit, shared with the decode workers
+struct CommitDecoder {
+    decoders: DecoderRegistry,
+    verifier: Option<CommitVerifier>,
+    verify_blocks: bool,
+    filter: RecordFilter,
+}
+
+impl CommitDecoder {
+    /// Filter the commit, then extract its records, verifying it first if a verifier is set.
+    ///
+    /// Returns `None` if the filter keeps nothing of the commit.
+    async fn decode(&self, commit: commit::Commit) -> Option<DecodedCommit> {
+        let commit = self
+            .filter
+            .apply(commit)?
+            .with_block_verification(self.verify_blocks);
+        let meta = Arc::new(commit.meta());
+        let records = match &self.verifier {
+            Some(verifier) => {
+                commit
+                    .try_extract_records_verified(&self.decoders, verifier)
+                    .await
+            }
+            None => commit.try_extract_records_with(&self.decoders).await,
+        };
+        Some((meta, records))
+    }
+}
+
 /// A helper for streaming events from the Firehose.
 ///
 /// This struct wraps a [`crate::RepoSubscription`] and provides a stream of [`commit::Record`]s,
@@ -72,16 +115,188 @@ impl PostStream {
 ///    println!("{:?}", record);
 /// }
 /// ```
-pub struct EventStream {
-    subscription: crate::RepoSubscription,
+pub struct EventStream<S = crate::RepoSubscription> {
+    subscription: S,
+    reconnect: Option<ReconnectPolicy>,
+    decoders: DecoderRegistry,
+    verifier: Option<CommitVerifier>,
+    verify_blocks: bool,
+    filter: RecordFilter,
+    /// How many commits may be decoded at once
+    parallelism: usize,
+    /// The `seq` of the last event handed to the consumer
+    cursor: Option<i64>,
 }
 
-impl EventStream {
-    /// Create a new [`EventStream`] from a [`crate::RepoSubscription`].
-    pub fn new(inner: crate::RepoSubscription) -> Self {
+impl<S: Subscription> EventStream<S> {
+    /// Create a new [`EventStream`] from a [`Subscription`], such as a [`crate::RepoSubscription`]
+    /// or a [`crate::replay::FileSubscription`].
+    pub fn new(inner: S) -> Self {
         EventStream {
             subscription: inner,
+            reconnect: None,
+            decoders: DecoderRegistry::default(),
+            verifier: None,
+            verify_blocks: true,
+            filter: RecordFilter::default(),
+            parallelism: 1,
+            cursor: None,
+        }
+    }
+
+    /// Get the `seq` of the last event this stream handed to the consumer, if any.
+    ///
+    /// With [`EventStream::with_parallelism`], events still being decoded are not counted,
+    /// so resuming from this cursor after dropping the stream loses nothing. Persist it
+    /// and pass it to [`crate::RepoSubscription::with_cursor`] to resume after a restart.
+    pub fn cursor(&self) -> Option<i64> {
+        self.cursor
+    }
+
+    /// Automatically reconnect when the connection drops or times out.
+    ///
+    /// The stream resumes from the last `seq` it has seen, so no events are lost
+    /// as long as the relay still has them in its backfill window.
+    pub fn with_reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+
+    /// Decode records of the collections registered in `decoders` with their
+    /// [`crate::types::custom::RecordDecoder`], yielding them as [`commit::Record::Custom`].
+    pub fn with_decoders(mut self, decoders: DecoderRegistry) -> Self {
+        self.decoders = decoders;
+        self
+    }
+
+    /// Verify every commit with `verifier` before extracting its records.
+    ///
+    /// Commits that fail verification yield no records. [`EventStream::try_stream`]
+    /// yields the failure as a [`crate::Error::Commit`], the other streams log it.
+    pub fn with_verifier(mut self, verifier: CommitVerifier) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Whether to check that every block matches its CID before decoding it.
+    ///
+    /// Enabled by default, see [`commit::Commit::with_block_verification`].
+    pub fn with_block_verification(mut self, enabled: bool) -> Self {
+        self.verify_blocks = enabled;
+        self
+    }
+
+    /// Only extract the records kept by `filter`.
+    ///
+    /// The filter is checked before a commit's blocks are read, so commits and operations
+    /// it drops cost no CAR parsing or record decoding. It applies to the record streams,
+    /// not to [`EventStream::stream_events`].
+    pub fn with_filter(mut self, filter: RecordFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Decode up to `workers` commits at once, each on its own Tokio task.
+    ///
+    /// Decoding the body of each `#commit` frame, CAR parsing, verification and record
+    /// decoding then run on the runtime's worker threads. The task reading the
+    /// subscription only reads the `seq` of commits, to resume from. Records are still
+    /// yielded in `seq` order. Once `workers` commits are being decoded or waiting for an
+    /// earlier one, the subscription is not read until the oldest is yielded, so a slow
+    /// consumer applies backpressure instead of buffering without bound.
+    ///
+    /// The default of 1 decodes every commit inline, which also works outside a Tokio
+    /// runtime. Higher values require a multi-threaded Tokio runtime to run in parallel.
+    pub fn with_parallelism(mut self, workers: usize) -> Self {
+        self.parallelism = workers.max(1);
+        self
+    }
+
+    /// Stream raw messages from the subscription, reconnecting according to the
+    /// [`ReconnectPolicy`] if one is set.
+    ///
+    /// If `defer_commits` is set, the bodies of commits are left for the caller to decode.
+    fn messages<'a>(
+        subscription: &'a mut S,
+        reconnect: Option<&'a ReconnectPolicy>,
+        defer_commits: bool,
+    ) -> impl futures::Stream<Item = Result<FirehoseMessage>> + 'a {
+        /// Track the cursor and whether the message ends the stream for good
+        fn observe(message: &Result<FirehoseMessage>, cursor: &mut Option<i64>) -> bool {
+            if let Ok(message) = message {
+                *cursor = message.seq().or(*cursor);
+            }
+            is_fatal(message)
         }
+
+        let state = (subscription, reconnect, false, None);
+        futures::stream::unfold(
+            state,
+            move |(subscription, policy, fatal, mut cursor)| async move {
+                if let Some(message) = crate::next_message(subscription, defer_commits).await {
+                    let fatal = observe(&message, &mut cursor);
+                    return Some((message, (subscription, policy, fatal, cursor)));
+                }
+                if fatal {
+                    return None;
+                }
+                let policy = policy?;
+                let mut attempts = 0;
+                loop {
+                    if !policy.should_retry(attempts) {
+                        tracing::error!(attempts, "Giving up reconnecting to the firehose");
+                        return None;
+                    }
+                    let event = ReconnectEvent {
+                        attempt: attempts + 1,
+                        delay: policy.delay(attempts),
+                        cursor,
+                    };
+                    policy.notify(&event);
+                    tokio::time::sleep(event.delay).await;
+                    attempts += 1;
+
+                    match subscription.resume(cursor).await {
+                        Ok(()) => {
+                            if let Some(message) =
+                                crate::next_message(subscription, defer_commits).await
+                            {
+                                let fatal = observe(&message, &mut cursor);
+                                return Some((
+                                    message,
+                                    (subscription, Some(policy), fatal, cursor),
+                                ));
+                            }
+                        }
+                        Err(e) => tracing::error!("Failed to reconnect: {}", e),
+                    }
+                }
+            },
+        )
+    }
+
+    /// Stream every event from the firehose without flattening commits.
+    ///
+    /// Unlike [`EventStream::stream`], this also yields identity, account, handle,
+    /// tombstone and info events.
+    pub async fn stream_events(&mut self) -> Result<impl futures::Stream<Item = Event> + '_> {
+        let cursor = &mut self.cursor;
+        let stream = Self::messages(&mut self.subscription, self.reconnect.as_ref(), false)
+            .inspect(move |result| {
+                if let Some(seq) = result.as_ref().ok().and_then(FirehoseMessage::seq) {
+                    *cursor = Some(seq);
+                }
+            })
+            .filter_map(|result| async {
+                match result.and_then(FirehoseMessage::into_event) {
+                    Ok(event) => Some(event),
+                    Err(e) => {
+                        tracing::error!("Error processing event: {}", e);
+                        None
+                    }
+                }
+            });
+        Ok(stream)
     }
 
     /// Start streaming events from the firehose,
@@ -90,16 +305,90 @@ impl EventStream {
     /// This function returns a [`futures::Stream`] of [`commit::Record`]s.
     ///
     pub async fn stream(&mut self) -> Result<impl futures::Stream<Item = commit::Record> + '_> {
-        let block_stream = self.subscription.stream_commits();
+        let stream = self
+            .stream_envelopes()
+            .await?
+            .map(|envelope| envelope.record);
+        Ok(stream)
+    }
 
-        let stream = block_stream
-            .await
-            .filter_map(|result| async {
+    /// Decode the commits of the subscription, on up to [`EventStream::with_parallelism`]
+    /// workers, in `seq` order. Other events are skipped, errors are passed through.
+    fn decoded_commits(&mut self) -> impl futures::Stream<Item = Result<DecodedCommit>> + '_ {
+        let decoder = Arc::new(CommitDecoder {
+            decoders: self.decoders.clone(),
+            verifier: self.verifier.clone(),
+            verify_blocks: self.verify_blocks,
+            filter: self.filter.clone(),
+        });
+        let parallelism = self.parallelism;
+        let cursor = &mut self.cursor;
+        // With workers, only the `seq` of commits is read here and their bodies are decoded
+        // by the workers. Inline, commits are decoded once, as they are read.
+        Self::messages(
+            &mut self.subscription,
+            self.reconnect.as_ref(),
+            parallelism > 1,
+        )
+        .map(move |result| {
+            let seq = result.as_ref().ok().and_then(FirehoseMessage::seq);
+            let is_commit = matches!(
+                result,
+                Ok(FirehoseMessage::Commit { .. } | FirehoseMessage::Event(Event::Commit(_)))
+            );
+            let decoder = decoder.clone();
+            let work = async move {
+                match result.and_then(FirehoseMessage::into_event)? {
+                    Event::Commit(commit) => Ok(decoder.decode(*commit).await),
+                    _ => Ok(None),
+                }
+            };
+            let work = if parallelism > 1 && is_commit {
+                Either::Left(tokio::spawn(work).map(|joined| {
+                    // The handle is never aborted, so the worker can only have panicked
+                    joined.unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+                }))
+            } else {
+                Either::Right(work)
+            };
+            work.map(move |result| (seq, result))
+        })
+        // `buffered` yields in the order the events were read, i.e. by `seq`
+        .buffered(parallelism)
+        .filter_map(move |(seq, result)| {
+            // Other events and filtered out commits still advance the cursor
+            if seq.is_some() {
+                *cursor = seq;
+            }
+            async move { result.transpose() }
+        })
+    }
+
+    /// Start streaming events from the firehose, like [`EventStream::stream`],
+    /// but keep the metadata of the commit each record was extracted from.
+    ///
+    /// This function returns a [`futures::Stream`] of [`commit::RecordEnvelope`]s.
+    pub async fn stream_envelopes(
+        &mut self,
+    ) -> Result<impl futures::Stream<Item = commit::RecordEnvelope> + '_> {
+        let stream = self
+            .decoded_commits()
+            .filter_map(|result| async move {
                 match result {
-                    Ok(commit_data) => {
-                        let commit = commit::Commit::from(&commit_data);
-                        let records = commit.extract_records().await;
-                        Some(futures::stream::iter(records.into_iter()))
+                    Ok((meta, records)) => {
+                        let records = records
+                            .into_iter()
+                            .filter_map(|record| {
+                                record
+                                    .inspect_err(|e| tracing::debug!("Skipping record: {}", e))
+                                    .ok()
+                            })
+                            .map(|record| commit::RecordEnvelope {
+                                commit: meta.clone(),
+                                record,
+                            })
+                            .collect::<Vec<_>>();
+                        Some(futures::stream::iter(records))
                     }
                     Err(e) => {
                         tracing::error!("Error processing commit: {}", e);
@@ -110,15 +399,40 @@ impl EventStream {
             .flatten();
         Ok(stream)
     }
+
+    /// Start streaming records from the firehose, like [`EventStream::stream`],
+    /// but yield errors instead of logging and skipping them.
+    ///
+    /// Records that fail to decode are yielded as [`crate::Error::Record`], carrying the
+    /// repository, operation path and CID of the record, while commits whose blocks
+    /// cannot be read at all are yielded as [`crate::Error::Commit`].
+    /// Connection and relay errors are yielded as they are.
+    pub async fn try_stream(
+        &mut self,
+    ) -> Result<impl futures::Stream<Item = Result<commit::Record>> + '_> {
+        let stream = self
+            .decoded_commits()
+            .map(|result| {
+                let records = match result {
+                    Ok((_, records)) => records,
+                    Err(e) => vec![Err(e)],
+                };
+                futures::stream::iter(records)
+            })
+            .flatten();
+        Ok(stream)
+    }
 }
 
 /// Simple helper function to create an [`EventStream`] from a domain directly.
 ///
+/// Returns an error if the relay cannot be reached.
+///
 /// ```no_run
 /// use futures::{pin_mut, StreamExt};
 /// use skystreamer::{stream::event_stream};
 ///
-/// let mut event_stream = event_stream("bsky.network").await.unwrap();
+/// let mut event_stream = event_stream("bsky.network").await?;
 /// let stream = event_stream.stream().await.unwrap();
 ///
 /// pin_mut!(stream);
@@ -127,7 +441,72 @@ impl EventStream {
 ///     // do something with your data here
 /// }
 /// ```
-pub async fn event_stream(domain: &str) -> EventStream {
-    let subscription = crate::RepoSubscription::new(domain).await.unwrap();
-    EventStream::new(subscription)
+pub async fn event_stream(domain: &str) -> Result<EventStream> {
+    let subscription = crate::RepoSubscription::new(domain).await?;
+    Ok(EventStream::new(subscription))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{CommitBuilder, FirehoseGenerator, MockRelay};
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn parallel_decode_keeps_order() {
+        async fn uris(parallelism: usize) -> Vec<String> {
+            let generator = FirehoseGenerator::new().max_ops_per_commit(8).limit(300);
+            let mut binding = EventStream::new(generator).with_parallelism(parallelism);
+            let stream = binding.try_stream().await.unwrap();
+            stream
+                .map(|record| record.unwrap().uri().to_string())
+                .collect()
+                .await
+        }
+
+        let sequential = uris(1).await;
+        assert!(sequential.len() > 300);
+        assert_eq!(uris(8).await, sequential);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn resume_from_cursor() {
+        let relay = MockRelay::start().await.unwrap();
+        let repo: atrium_api::types::string::Did =
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap();
+        let subscription = relay.subscription().connect().await.unwrap();
+        relay.wait_for_connections(1).await;
+        for rkey in ["3l6oveex3ii2a", "3l6oveex3ii2b", "3l6oveex3ii2c"] {
+            let commit = CommitBuilder::new(repo.clone()).post(rkey, "hello").build();
+            relay.push_commit(commit).unwrap();
+        }
+
+        let mut binding = EventStream::new(subscription).with_parallelism(4);
+        let records = binding
+            .stream()
+            .await
+            .unwrap()
+            .take(2)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(records.len(), 2);
+        // Read ahead commits are still being decoded, and not counted
+        assert_eq!(binding.cursor(), Some(2));
+
+        let subscription = relay
+            .subscription()
+            .cursor(binding.cursor().unwrap())
+            .connect()
+            .await
+            .unwrap();
+        let mut binding = EventStream::new(subscription).with_parallelism(4);
+        let records = binding
+            .stream()
+            .await
+            .unwrap()
+            .take(1)
+            .collect::<Vec<_>>()
+            .await;
+        assert!(records[0].uri().to_string().ends_with("/3l6oveex3ii2c"));
+        assert_eq!(binding.cursor(), Some(3));
+    }
 }
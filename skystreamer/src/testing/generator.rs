@@ -0,0 +1,525 @@
+//! A synthetic firehose, for load testing consumers without the live network.
+use super::{cid_for, CommitBuilder};
+use crate::{
+    types::{Frame, Subscription},
+    Result,
+};
+use atrium_api::{
+    app::bsky::{
+        embed::{external, images, record},
+        feed::{self, post::RecordEmbedRefs},
+    },
+    com::atproto::{repo::strong_ref, sync::subscribe_repos::Commit as ACommit},
+    types::{
+        string::{Datetime, Did},
+        Blob, BlobRef, CidLink, Collection, TypedBlobRef, Union,
+    },
+};
+use cid::{multihash::Multihash, Cid};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use tokio::time::{Duration, Instant};
+
+/// The alphabet of `did:plc` identifiers and record keys
+const BASE32_SORTABLE: &[u8; 32] = b"234567abcdefghijklmnopqrstuvwxyz";
+
+/// How many of a repository's records are remembered as candidates for deletion
+const DELETABLE_PER_REPO: usize = 16;
+
+/// How many recent posts are remembered as subjects for likes and quotes
+const RECENT_POSTS: usize = 1024;
+
+const WORDS: &[&str] = &[
+    "the", "firehose", "is", "a", "stream", "of", "every", "commit", "on", "network", "today",
+    "rust", "bluesky", "post", "with", "some", "words", "and", "🦀", "héllo", "#atproto",
+];
+
+/// The relative weights of the operations a [`FirehoseGenerator`] produces.
+///
+/// Each operation of a commit is picked at random with these weights, so a mix of
+/// `posts: 1, likes: 3` produces about three likes for every post.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperationMix {
+    /// Plain text posts
+    pub posts: u32,
+    /// Posts embedding images, an external link or a quoted post
+    pub embeds: u32,
+    pub likes: u32,
+    pub follows: u32,
+    /// Deletions of records created earlier by the same repository
+    pub deletes: u32,
+}
+
+impl Default for OperationMix {
+    /// Roughly the mix of the Bluesky firehose
+    fn default() -> Self {
+        Self {
+            posts: 12,
+            embeds: 8,
+            likes: 55,
+            follows: 15,
+            deletes: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OpKind {
+    Post,
+    Embed,
+    Like,
+    Follow,
+    Delete,
+}
+
+impl OperationMix {
+    fn pick(&self, rng: &mut impl Rng) -> OpKind {
+        let weights = [
+            (OpKind::Post, self.posts),
+            (OpKind::Embed, self.embeds),
+            (OpKind::Like, self.likes),
+            (OpKind::Follow, self.follows),
+            (OpKind::Delete, self.deletes),
+        ];
+        let total: u32 = weights.iter().map(|(_, weight)| weight).sum();
+        if total == 0 {
+            return OpKind::Post;
+        }
+        let mut roll = rng.gen_range(0..total);
+        for (kind, weight) in weights {
+            if roll < weight {
+                return kind;
+            }
+            roll -= weight;
+        }
+        unreachable!("roll is below the total weight")
+    }
+}
+
+/// Generates realistic `#commit` events, with real records, CAR slices and CIDs.
+///
+/// The generator is a [`Subscription`], so it can be wrapped in an
+/// [`EventStream`](crate::stream::EventStream) directly, or its frames can be encoded with
+/// [`Frame::to_bytes`] and pushed to a [`MockRelay`](super::MockRelay). Output is
+/// deterministic for a given seed, apart from timestamps.
+///
+/// # Example
+/// ```no_run
+/// use skystreamer::{stream::EventStream, testing::{FirehoseGenerator, OperationMix}};
+///
+/// // Five times peak Bluesky volume, for a minute
+/// let generator = FirehoseGenerator::new()
+///     .mix(OperationMix { deletes: 0, ..Default::default() })
+///     .rate(10_000.0)
+///     .limit(600_000);
+/// let mut binding = EventStream::new(generator);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FirehoseGenerator {
+    mix: OperationMix,
+    repos: Vec<Did>,
+    max_ops: usize,
+    rng: StdRng,
+    /// The `seq` of the last commit generated
+    seq: i64,
+    /// How many records were created, to give each a unique record key
+    records: u64,
+    /// How many commits were generated, to give each a strictly increasing revision
+    revs: u64,
+    /// Records each repository created, which can be deleted later
+    deletable: Vec<Vec<(String, String)>>,
+    /// URIs and CIDs of recent posts, to like and quote
+    recent_posts: VecDeque<(String, Cid)>,
+    rate: Option<f64>,
+    limit: Option<u64>,
+    generated: u64,
+    started: Option<Instant>,
+}
+
+impl Default for FirehoseGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FirehoseGenerator {
+    /// Create a generator with the default mix, 1000 repositories and up to 3 operations per commit
+    pub fn new() -> Self {
+        Self {
+            mix: OperationMix::default(),
+            repos: vec![],
+            max_ops: 3,
+            rng: StdRng::seed_from_u64(0),
+            seq: 0,
+            records: 0,
+            revs: 0,
+            deletable: vec![],
+            recent_posts: VecDeque::new(),
+            rate: None,
+            limit: None,
+            generated: 0,
+            started: None,
+        }
+        .repos(1000)
+    }
+
+    /// Set the mix of operations to generate
+    pub fn mix(mut self, mix: OperationMix) -> Self {
+        self.mix = mix;
+        self
+    }
+
+    /// Spread commits over `count` repositories
+    pub fn repos(mut self, count: usize) -> Self {
+        self.repos = (0..count.max(1) as u64).map(plc_did).collect();
+        self.deletable = vec![vec![]; self.repos.len()];
+        self
+    }
+
+    /// Give each commit between 1 and `max` operations
+    pub fn max_ops_per_commit(mut self, max: usize) -> Self {
+        self.max_ops = max.max(1);
+        self
+    }
+
+    /// Seed the random choices of the generator
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Produce at most `commits_per_second` commits when used as a [`Subscription`].
+    ///
+    /// Without a rate, commits are produced as fast as they are consumed.
+    pub fn rate(mut self, commits_per_second: f64) -> Self {
+        self.rate = Some(commits_per_second).filter(|rate| *rate > 0.0);
+        self
+    }
+
+    /// End the [`Subscription`] after `commits` commits
+    pub fn limit(mut self, commits: u64) -> Self {
+        self.limit = Some(commits);
+        self
+    }
+
+    /// Generate the next commit, with the next `seq`
+    pub fn next_commit(&mut self) -> ACommit {
+        let index = self.rng.gen_range(0..self.repos.len());
+        let repo = self.repos[index].clone();
+        let mut builder = CommitBuilder::new(repo.clone()).rev(&tid(self.revs));
+        self.revs += 1;
+
+        // Records created by this commit, which only later commits may delete
+        let mut created = vec![];
+        for _ in 0..self.rng.gen_range(1..=self.max_ops) {
+            let mut kind = self.mix.pick(&mut self.rng);
+            if kind == OpKind::Delete && self.deletable[index].is_empty() {
+                kind = OpKind::Post;
+            }
+            if kind == OpKind::Delete {
+                let deletable = &mut self.deletable[index];
+                let (collection, rkey) =
+                    deletable.swap_remove(self.rng.gen_range(0..deletable.len()));
+                builder = builder.delete(&collection, &rkey);
+                continue;
+            }
+
+            let rkey = tid(self.records);
+            self.records += 1;
+            let collection = match kind {
+                OpKind::Post | OpKind::Embed => {
+                    let embed = (kind == OpKind::Embed).then(|| self.embed());
+                    builder = builder.post_with(&rkey, self.post(embed));
+                    feed::Post::NSID
+                }
+                OpKind::Like => {
+                    let (uri, cid) = self.subject();
+                    builder = builder.like(&rkey, &uri, cid);
+                    feed::Like::NSID
+                }
+                OpKind::Follow => {
+                    let subject = self.repos[self.rng.gen_range(0..self.repos.len())].clone();
+                    builder = builder.follow(&rkey, subject);
+                    atrium_api::app::bsky::graph::Follow::NSID
+                }
+                OpKind::Delete => unreachable!("deletes are handled above"),
+            };
+            created.push((collection.to_string(), rkey));
+        }
+        let deletable = &mut self.deletable[index];
+        for record in created {
+            if deletable.len() >= DELETABLE_PER_REPO {
+                deletable.remove(0);
+            }
+            deletable.push(record);
+        }
+
+        let mut commit = builder.build();
+        self.seq += 1;
+        commit.seq = self.seq;
+
+        for op in &commit.ops {
+            if op.action != "create" || !op.path.starts_with(feed::Post::NSID) {
+                continue;
+            }
+            if let Some(cid) = &op.cid {
+                if self.recent_posts.len() >= RECENT_POSTS {
+                    self.recent_posts.pop_front();
+                }
+                self.recent_posts
+                    .push_back((format!("at://{}/{}", repo.as_str(), op.path), cid.0));
+            }
+        }
+        commit
+    }
+
+    /// Generate the next commit as a `#commit` frame
+    pub fn next_frame(&mut self) -> Result<Frame> {
+        let commit = self.next_commit();
+        Frame::message("#commit", &commit.data)
+    }
+
+    fn post(&mut self, embed: Option<Union<RecordEmbedRefs>>) -> feed::post::RecordData {
+        let words = self.rng.gen_range(3..40);
+        let text = (0..words)
+            .map(|_| WORDS[self.rng.gen_range(0..WORDS.len())])
+            .collect::<Vec<_>>()
+            .join(" ");
+        feed::post::RecordData {
+            created_at: Datetime::now(),
+            embed,
+            entities: None,
+            facets: None,
+            labels: None,
+            langs: Some(vec!["en".parse().expect("valid language tag")]),
+            reply: None,
+            tags: None,
+            text,
+        }
+    }
+
+    fn embed(&mut self) -> Union<RecordEmbedRefs> {
+        let embed = match self.rng.gen_range(0..3) {
+            0 => {
+                let images = (0..self.rng.gen_range(1..=4))
+                    .map(|_| {
+                        images::ImageData {
+                            alt: String::new(),
+                            aspect_ratio: None,
+                            image: BlobRef::Typed(TypedBlobRef::Blob(Blob {
+                                r#ref: CidLink(blob_cid(self.rng.gen())),
+                                mime_type: "image/jpeg".to_string(),
+                                size: self.rng.gen_range(10_000..1_000_000),
+                            })),
+                        }
+                        .into()
+                    })
+                    .collect();
+                RecordEmbedRefs::AppBskyEmbedImagesMain(Box::new(
+                    images::MainData { images }.into(),
+                ))
+            }
+            1 if !self.recent_posts.is_empty() => {
+                let (uri, cid) = self.subject();
+                RecordEmbedRefs::AppBskyEmbedRecordMain(Box::new(
+                    record::MainData {
+                        record: strong_ref::MainData {
+                            cid: atrium_api::types::string::Cid::new(cid),
+                            uri,
+                        }
+                        .into(),
+                    }
+                    .into(),
+                ))
+            }
+            _ => RecordEmbedRefs::AppBskyEmbedExternalMain(Box::new(
+                external::MainData {
+                    external: external::ExternalData {
+                        description: "A link to somewhere else".to_string(),
+                        thumb: None,
+                        title: "Somewhere else".to_string(),
+                        uri: format!("https://example.com/{}", self.rng.gen::<u32>()),
+                    }
+                    .into(),
+                }
+                .into(),
+            )),
+        };
+        Union::Refs(embed)
+    }
+
+    /// A recent post to like or quote, or a made up one if there are none yet
+    fn subject(&mut self) -> (String, Cid) {
+        if self.recent_posts.is_empty() {
+            let did = plc_did(self.rng.gen());
+            return (
+                format!("at://{}/{}/{}", did.as_str(), feed::Post::NSID, tid(0)),
+                cid_for(&self.rng.gen::<u64>().to_be_bytes()),
+            );
+        }
+        self.recent_posts[self.rng.gen_range(0..self.recent_posts.len())].clone()
+    }
+}
+
+impl Subscription for FirehoseGenerator {
+    /// Generate the next `#commit` frame, waiting for the target rate if one is set
+    async fn next(&mut self) -> Option<Result<Frame>> {
+        if self.limit.is_some_and(|limit| self.generated >= limit) {
+            return None;
+        }
+        if let Some(rate) = self.rate {
+            let started = *self.started.get_or_insert_with(Instant::now);
+            let due = started + Duration::from_secs_f64(self.generated as f64 / rate);
+            tokio::time::sleep_until(due).await;
+        }
+        self.generated += 1;
+        Some(self.next_frame())
+    }
+
+    /// Continue the sequence after `cursor`. Generated commits can always be resumed.
+    async fn resume(&mut self, cursor: Option<i64>) -> Result<()> {
+        if let Some(cursor) = cursor {
+            self.seq = cursor;
+        }
+        Ok(())
+    }
+}
+
+/// A `did:plc` identifier unique to `n`
+fn plc_did(n: u64) -> Did {
+    let mut id = [b'a'; 24];
+    let mut rest = n;
+    for byte in id.iter_mut().rev() {
+        *byte = BASE32_SORTABLE[(rest % 32) as usize];
+        rest /= 32;
+        if rest == 0 {
+            break;
+        }
+    }
+    let id = std::str::from_utf8(&id).expect("base32 is ASCII");
+    Did::new(format!("did:plc:{id}")).expect("valid DID")
+}
+
+/// A timestamp identifier, used as record key and revision, increasing with `n`
+fn tid(n: u64) -> String {
+    // Microseconds since the epoch, starting at 2024-10-17, with an empty clock identifier
+    let value = (1_729_166_400_000_000 + n) << 10;
+    (0..13)
+        .rev()
+        .map(|i| BASE32_SORTABLE[((value >> (i * 5)) & 31) as usize] as char)
+        .collect()
+}
+
+/// The CID of a blob, as referenced by image embeds
+fn blob_cid(seed: u64) -> Cid {
+    let digest = Sha256::digest(seed.to_be_bytes());
+    Cid::new_v1(
+        0x55,
+        Multihash::wrap(0x12, &digest).expect("SHA-256 digest fits"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        stream::EventStream,
+        types::commit::{Commit, Record},
+    };
+    use futures::StreamExt;
+
+    #[test]
+    fn generated_commits_decode() {
+        let mut generator = FirehoseGenerator::new().repos(3).max_ops_per_commit(5);
+        for seq in 1..=200 {
+            let commit = generator.next_commit();
+            assert_eq!(commit.seq, seq);
+            let commit = Commit::from(commit);
+            for record in futures::executor::block_on(commit.try_extract_records()) {
+                record.unwrap();
+            }
+        }
+
+        // Only likes
+        let mix = OperationMix {
+            posts: 0,
+            embeds: 0,
+            likes: 1,
+            follows: 0,
+            deletes: 0,
+        };
+        let generator = FirehoseGenerator::new().mix(mix).limit(10);
+        let mut binding = EventStream::new(generator);
+        let records = futures::executor::block_on(async {
+            binding.stream().await.unwrap().collect::<Vec<_>>().await
+        });
+        assert!(!records.is_empty());
+        assert!(records
+            .iter()
+            .all(|record| matches!(record, Record::Like(_))));
+    }
+
+    #[test]
+    fn revisions_increase() {
+        let mix = OperationMix {
+            posts: 1,
+            embeds: 0,
+            likes: 1,
+            follows: 0,
+            deletes: 3,
+        };
+        let mut generator = FirehoseGenerator::new()
+            .repos(1)
+            .max_ops_per_commit(1)
+            .mix(mix);
+        let revs = (0..100)
+            .map(|_| generator.next_commit().rev.clone())
+            .collect::<Vec<_>>();
+        assert!(revs.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn commits_do_not_delete_their_own_records() {
+        let mix = OperationMix {
+            posts: 1,
+            embeds: 0,
+            likes: 0,
+            follows: 0,
+            deletes: 1,
+        };
+        let mut generator = FirehoseGenerator::new()
+            .repos(1)
+            .max_ops_per_commit(8)
+            .mix(mix);
+        for _ in 0..100 {
+            let commit = generator.next_commit();
+            let created = commit
+                .ops
+                .iter()
+                .filter(|op| op.action == "create")
+                .map(|op| op.path.as_str())
+                .collect::<Vec<_>>();
+            assert!(commit
+                .ops
+                .iter()
+                .filter(|op| op.action == "delete")
+                .all(|op| !created.contains(&op.path.as_str())));
+        }
+    }
+
+    #[test]
+    fn made_up_subjects_are_records() {
+        let mut generator = FirehoseGenerator::new();
+        let (_, cid) = generator.subject();
+        assert_eq!(cid.codec(), 0x71);
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(tid(0).len(), 13);
+        assert!(tid(1) > tid(0));
+        assert_eq!(plc_did(0).as_str(), "did:plc:aaaaaaaaaaaaaaaaaaaaaaa2");
+        assert_ne!(plc_did(1), plc_did(32));
+    }
+}
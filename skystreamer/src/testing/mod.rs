@@ -0,0 +1,696 @@
+//! Support for testing firehose consumers without a real relay.
+//!
+//! Enabled with the `test-support` feature. [`MockRelay`] is a local websocket server
+//! speaking `com.atproto.sync.subscribeRepos`, and [`CommitBuilder`] produces synthetic
+//! commits whose blocks are a valid CAR slice, so they decode (and verify) exactly like
+//! commits from the network.
+//!
+//! # Example
+//! ```no_run
+//! use skystreamer::{stream::EventStream, testing::{CommitBuilder, MockRelay}};
+//!
+//! let relay = MockRelay::start().await?;
+//! let repo = "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap();
+//! relay.push_commit(CommitBuilder::new(repo).post("3l6oveex3ii2l", "Hello, world!").build());
+//!
+//! // Replay everything the relay has, then follow live commits
+//! let subscription = relay.subscription().cursor(0).connect().await?;
+//! let mut binding = EventStream::new(subscription);
+//! ```
+//!
+//! For load testing, [`FirehoseGenerator`] produces an endless stream of such commits
+//! with a configurable mix of operations, at a target rate.
+mod generator;
+
+pub use generator::{FirehoseGenerator, OperationMix};
+
+use crate::{
+    builder::{RepoSubscriptionBuilder, Scheme},
+    types::{ErrorFrame, Frame},
+    verify::{MstEntry, MstNode},
+    RepoSubscription, Result,
+};
+use atrium_api::{
+    app::bsky::{feed, graph},
+    com::atproto::{
+        repo::strong_ref,
+        sync::subscribe_repos::{Commit as ACommit, CommitData, RepoOpData},
+    },
+    types::{
+        string::{Datetime, Did},
+        CidLink, Collection,
+    },
+};
+use cid::{multihash::Multihash, Cid};
+use futures::SinkExt;
+use ipld_core::ipld::Ipld;
+use k256::ecdsa::{signature::Signer, SigningKey};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::BTreeMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{broadcast, Notify},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::{
+    handshake::server::{Request, Response},
+    Message,
+};
+
+/// How many live frames a connection may fall behind before the relay drops it
+const LIVE_BUFFER: usize = 1024;
+
+/// The rev used by [`CommitBuilder`] unless another is set
+const DEFAULT_REV: &str = "3l6oveex3ii2l";
+
+/// Compute the CIDv1 of a DAG-CBOR block, as used for records and repo nodes
+pub fn cid_for(block: &[u8]) -> Cid {
+    let digest = Sha256::digest(block);
+    Cid::new_v1(
+        0x71,
+        Multihash::wrap(0x12, &digest).expect("SHA-256 digest fits"),
+    )
+}
+
+fn write_varint(mut n: usize, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+/// Encode `blocks` as a CARv1 file with a single `root`
+pub fn car(root: Cid, blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+    let header = Ipld::Map(
+        [
+            ("roots".to_string(), Ipld::List(vec![Ipld::Link(root)])),
+            ("version".to_string(), Ipld::Integer(1)),
+        ]
+        .into_iter()
+        .collect(),
+    );
+    let header = serde_ipld_dagcbor::to_vec(&header).expect("CAR header encodes");
+    let mut car = vec![];
+    write_varint(header.len(), &mut car);
+    car.extend(header);
+    for (cid, block) in blocks {
+        let cid = cid.to_bytes();
+        write_varint(cid.len() + block.len(), &mut car);
+        car.extend(cid);
+        car.extend(block);
+    }
+    car
+}
+
+/// Encode a message frame of type `t` (e.g. `#commit`) with the given body
+pub fn message_frame(t: &str, body: &impl Serialize) -> Result<Vec<u8>> {
+    Frame::message(t, body)?.to_bytes()
+}
+
+/// Encode an error frame, like the `FutureCursor` error a relay sends before closing
+pub fn error_frame(error: &str, message: Option<&str>) -> Result<Vec<u8>> {
+    Frame::Error(ErrorFrame {
+        error: error.to_string(),
+        message: message.map(str::to_string),
+    })
+    .to_bytes()
+}
+
+/// Builds a synthetic commit, with its records, MST and signed commit object in a CAR slice.
+///
+/// The MST is a single node holding the records written by the commit, which is enough
+/// to prove its operations, but is not laid out like the MST of a real repository.
+#[derive(Debug, Clone)]
+pub struct CommitBuilder {
+    repo: Did,
+    rev: String,
+    since: Option<String>,
+    ops: Vec<RepoOpData>,
+    blocks: Vec<(Cid, Vec<u8>)>,
+    /// The records in the repository after the commit, by path
+    entries: BTreeMap<String, Cid>,
+    key: Option<SigningKey>,
+}
+
+impl CommitBuilder {
+    /// Start a commit to `repo`
+    pub fn new(repo: Did) -> Self {
+        Self {
+            repo,
+            rev: DEFAULT_REV.to_string(),
+            since: None,
+            ops: vec![],
+            blocks: vec![],
+            entries: BTreeMap::new(),
+            key: None,
+        }
+    }
+
+    /// Set the revision of the commit
+    pub fn rev(mut self, rev: &str) -> Self {
+        self.rev = rev.to_string();
+        self
+    }
+
+    /// Set the revision of the previous commit
+    pub fn since(mut self, rev: &str) -> Self {
+        self.since = Some(rev.to_string());
+        self
+    }
+
+    /// Sign the commit object with `key`. Unsigned commits carry an empty signature.
+    pub fn signing_key(mut self, key: SigningKey) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Create a record at `collection/rkey`.
+    ///
+    /// # Panics
+    /// If `record` cannot be encoded as DAG-CBOR.
+    pub fn create(self, collection: &str, rkey: &str, record: &impl Serialize) -> Self {
+        self.write("create", collection, rkey, record)
+    }
+
+    /// Update the record at `collection/rkey`.
+    ///
+    /// # Panics
+    /// If `record` cannot be encoded as DAG-CBOR.
+    pub fn update(self, collection: &str, rkey: &str, record: &impl Serialize) -> Self {
+        self.write("update", collection, rkey, record)
+    }
+
+    /// Delete the record at `collection/rkey`
+    pub fn delete(mut self, collection: &str, rkey: &str) -> Self {
+        let path = format!("{collection}/{rkey}");
+        self.entries.remove(&path);
+        self.ops.push(RepoOpData {
+            action: "delete".to_string(),
+            cid: None,
+            path,
+        });
+        self
+    }
+
+    /// Create an `app.bsky.feed.post` with the given text
+    pub fn post(self, rkey: &str, text: &str) -> Self {
+        self.post_with(
+            rkey,
+            feed::post::RecordData {
+                created_at: Datetime::now(),
+                embed: None,
+                entities: None,
+                facets: None,
+                labels: None,
+                langs: None,
+                reply: None,
+                tags: None,
+                text: text.to_string(),
+            },
+        )
+    }
+
+    /// Create an `app.bsky.feed.post`, e.g. one with an embed or reply
+    pub fn post_with(self, rkey: &str, record: feed::post::RecordData) -> Self {
+        self.create_record(feed::Post::NSID, rkey, record)
+    }
+
+    /// Create an `app.bsky.feed.like` of the record at `uri`
+    pub fn like(self, rkey: &str, uri: &str, cid: Cid) -> Self {
+        let record = feed::like::RecordData {
+            created_at: Datetime::now(),
+            subject: strong_ref::MainData {
+                cid: atrium_api::types::string::Cid::new(cid),
+                uri: uri.to_string(),
+            }
+            .into(),
+        };
+        self.create_record(feed::Like::NSID, rkey, record)
+    }
+
+    /// Create an `app.bsky.graph.follow` of `subject`
+    pub fn follow(self, rkey: &str, subject: Did) -> Self {
+        let record = graph::follow::RecordData {
+            created_at: Datetime::now(),
+            subject,
+        };
+        self.create_record(graph::Follow::NSID, rkey, record)
+    }
+
+    /// Create a record of a known type, adding its `$type`
+    fn create_record<T>(self, collection: &str, rkey: &str, record: T) -> Self
+    where
+        atrium_api::types::Object<T>: Serialize,
+    {
+        let mut record = ipld_core::serde::to_ipld(atrium_api::types::Object::from(record))
+            .expect("record converts to IPLD");
+        if let Ipld::Map(map) = &mut record {
+            map.insert("$type".to_string(), Ipld::String(collection.to_string()));
+        }
+        self.create(collection, rkey, &record)
+    }
+
+    fn write(
+        mut self,
+        action: &str,
+        collection: &str,
+        rkey: &str,
+        record: &impl Serialize,
+    ) -> Self {
+        let block = serde_ipld_dagcbor::to_vec(record).expect("record encodes to DAG-CBOR");
+        let cid = cid_for(&block);
+        let path = format!("{collection}/{rkey}");
+        self.blocks.push((cid, block));
+        self.entries.insert(path.clone(), cid);
+        self.ops.push(RepoOpData {
+            action: action.to_string(),
+            cid: Some(CidLink(cid)),
+            path,
+        });
+        self
+    }
+
+    /// Finish the commit. Its `seq` is 0 until it is pushed to a [`MockRelay`].
+    pub fn build(self) -> ACommit {
+        let mut blocks = self.blocks;
+
+        let mut entries = vec![];
+        let mut previous: &[u8] = &[];
+        for (path, cid) in &self.entries {
+            let shared = previous
+                .iter()
+                .zip(path.as_bytes())
+                .take_while(|(a, b)| a == b)
+                .count();
+            entries.push(MstEntry {
+                p: shared,
+                k: path.as_bytes()[shared..].to_vec(),
+                v: *cid,
+                t: None,
+            });
+            previous = path.as_bytes();
+        }
+        let node = serde_ipld_dagcbor::to_vec(&MstNode {
+            l: None,
+            e: entries,
+        })
+        .expect("MST node encodes");
+        let data = cid_for(&node);
+        blocks.push((data, node));
+
+        let mut object: BTreeMap<String, Ipld> = [
+            (
+                "did".to_string(),
+                Ipld::String(self.repo.as_str().to_string()),
+            ),
+            ("version".to_string(), Ipld::Integer(3)),
+            ("data".to_string(), Ipld::Link(data)),
+            ("rev".to_string(), Ipld::String(self.rev.clone())),
+            ("prev".to_string(), Ipld::Null),
+        ]
+        .into_iter()
+        .collect();
+        let sig = match &self.key {
+            Some(key) => {
+                let unsigned = serde_ipld_dagcbor::to_vec(&Ipld::Map(object.clone()))
+                    .expect("commit object encodes");
+                let sig: k256::ecdsa::Signature = key.sign(&unsigned);
+                sig.to_bytes().to_vec()
+            }
+            None => vec![],
+        };
+        object.insert("sig".to_string(), Ipld::Bytes(sig));
+        let object = serde_ipld_dagcbor::to_vec(&Ipld::Map(object)).expect("commit object encodes");
+        let commit = cid_for(&object);
+        blocks.push((commit, object));
+
+        CommitData {
+            blobs: vec![],
+            blocks: car(commit, &blocks),
+            commit: CidLink(commit),
+            ops: self.ops.into_iter().map(Into::into).collect(),
+            prev: None,
+            rebase: false,
+            repo: self.repo,
+            rev: self.rev,
+            seq: 0,
+            since: self.since,
+            time: Datetime::now(),
+            too_big: false,
+        }
+        .into()
+    }
+}
+
+/// A frame sent to every connected client
+#[derive(Debug, Clone)]
+enum Outgoing {
+    /// A sequenced event, which is also kept for clients connecting with a cursor
+    Event(i64, Arc<Vec<u8>>),
+    /// A frame outside the sequence, sent only to current clients
+    Frame(Arc<Vec<u8>>),
+    /// An error frame, after which the connection is closed
+    Error(Arc<Vec<u8>>),
+    /// Close the connection
+    Close,
+}
+
+#[derive(Default)]
+struct RelayState {
+    /// Every sequenced event pushed so far, in order
+    events: Vec<(i64, Arc<Vec<u8>>)>,
+    /// An error frame sent to new connections instead of any events
+    reject: Option<Arc<Vec<u8>>>,
+    connections: usize,
+    /// Notified whenever a client has connected and subscribed
+    connected: Arc<Notify>,
+}
+
+/// A local relay serving `com.atproto.sync.subscribeRepos` over plain websockets.
+///
+/// Frames pushed to the relay are sent to every connected client. Sequenced events are
+/// also kept, and replayed to clients that connect with a `cursor`, like a relay's
+/// backfill window covering everything. Clients without a cursor only get live events,
+/// and a cursor ahead of the last event is answered with a `FutureCursor` error.
+///
+/// The relay shuts down when dropped.
+pub struct MockRelay {
+    addr: SocketAddr,
+    state: Arc<Mutex<RelayState>>,
+    live: broadcast::Sender<Outgoing>,
+    task: JoinHandle<()>,
+}
+
+impl MockRelay {
+    /// Start a relay on a free local port
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(RelayState::default()));
+        let (live, _) = broadcast::channel(LIVE_BUFFER);
+        let task = tokio::spawn(accept(listener, state.clone(), live.clone()));
+        Ok(Self {
+            addr,
+            state,
+            live,
+            task,
+        })
+    }
+
+    /// The address the relay is listening on
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The `host:port` of the relay, to pass to [`RepoSubscription::builder`]
+    pub fn host(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// A builder for a subscription to this relay
+    pub fn subscription(&self) -> RepoSubscriptionBuilder {
+        RepoSubscription::builder(&self.host()).scheme(Scheme::Ws)
+    }
+
+    /// The `seq` of the last event pushed, or 0 if there is none
+    pub fn latest_seq(&self) -> i64 {
+        let state = self.state.lock().unwrap();
+        state.events.last().map_or(0, |(seq, _)| *seq)
+    }
+
+    /// How many clients have connected so far, including ones that disconnected
+    pub fn connections(&self) -> usize {
+        self.state.lock().unwrap().connections
+    }
+
+    /// Wait until `count` clients have connected, and will receive everything pushed from now on
+    pub async fn wait_for_connections(&self, count: usize) {
+        let connected = self.state.lock().unwrap().connected.clone();
+        loop {
+            let notified = connected.notified();
+            if self.connections() >= count {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Push a commit as the next event, returning the `seq` it was given
+    pub fn push_commit(&self, mut commit: ACommit) -> Result<i64> {
+        self.push_event("#commit", |seq| {
+            commit.seq = seq;
+            message_frame("#commit", &commit.data)
+        })
+    }
+
+    /// Push a message of type `t` as the next event. `body` is given the event's `seq`.
+    pub fn push_event(&self, t: &str, body: impl FnOnce(i64) -> Result<Vec<u8>>) -> Result<i64> {
+        let mut state = self.state.lock().unwrap();
+        let seq = state.events.last().map_or(0, |(seq, _)| *seq) + 1;
+        let frame = Arc::new(body(seq)?);
+        tracing::trace!(seq, t, "Pushing event to mock relay");
+        state.events.push((seq, frame.clone()));
+        // Sent while holding the lock, so connecting clients see each event exactly once
+        let _ = self.live.send(Outgoing::Event(seq, frame));
+        Ok(seq)
+    }
+
+    /// Send a raw frame to every connected client, without giving it a `seq`
+    pub fn push_frame(&self, frame: Vec<u8>) {
+        let _state = self.state.lock().unwrap();
+        let _ = self.live.send(Outgoing::Frame(Arc::new(frame)));
+    }
+
+    /// Send an error frame to every connected client, then close their connections
+    pub fn send_error(&self, error: &str, message: Option<&str>) -> Result<()> {
+        let frame = Arc::new(error_frame(error, message)?);
+        let _state = self.state.lock().unwrap();
+        let _ = self.live.send(Outgoing::Error(frame));
+        Ok(())
+    }
+
+    /// Answer every new connection with an error frame, or accept them again with `None`
+    pub fn reject_connections(&self, error: Option<(&str, Option<&str>)>) -> Result<()> {
+        let frame = match error {
+            Some((error, message)) => Some(Arc::new(error_frame(error, message)?)),
+            None => None,
+        };
+        self.state.lock().unwrap().reject = frame;
+        Ok(())
+    }
+
+    /// Close every current connection, as a relay restart would
+    pub fn disconnect(&self) {
+        let _state = self.state.lock().unwrap();
+        let _ = self.live.send(Outgoing::Close);
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        let _ = self.live.send(Outgoing::Close);
+        self.task.abort();
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    state: Arc<Mutex<RelayState>>,
+    live: broadcast::Sender<Outgoing>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(serve(stream, state.clone(), live.clone()));
+            }
+            Err(e) => {
+                tracing::error!("Mock relay failed to accept a connection: {}", e);
+                return;
+            }
+        }
+    }
+}
+
+/// Serve a single subscriber until it disconnects or the relay closes the connection
+// The handshake callback's error type is set by tungstenite
+#[allow(clippy::result_large_err)]
+async fn serve(
+    stream: TcpStream,
+    state: Arc<Mutex<RelayState>>,
+    live: broadcast::Sender<Outgoing>,
+) {
+    let mut cursor = None;
+    let handshake = tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response| {
+        cursor = request.uri().query().and_then(|query| {
+            url::form_urlencoded::parse(query.as_bytes())
+                .find(|(key, _)| key == "cursor")
+                .and_then(|(_, value)| value.parse::<i64>().ok())
+        });
+        Ok::<Response, _>(response)
+    });
+    let mut ws = match handshake.await {
+        Ok(ws) => ws,
+        Err(e) => {
+            tracing::warn!("Mock relay handshake failed: {}", e);
+            return;
+        }
+    };
+
+    // Subscribe and snapshot the backlog under the lock, so no event is missed or repeated
+    let (mut live, backlog, rejection) = {
+        let mut state = state.lock().unwrap();
+        state.connections += 1;
+        state.connected.notify_waiters();
+        let latest = state.events.last().map_or(0, |(seq, _)| *seq);
+        let rejection = match cursor {
+            _ if state.reject.is_some() => state.reject.clone(),
+            Some(cursor) if cursor > latest => Some(Arc::new(
+                error_frame("FutureCursor", Some("Cursor in the future."))
+                    .expect("error frame encodes"),
+            )),
+            _ => None,
+        };
+        let backlog = match cursor {
+            Some(cursor) => state
+                .events
+                .iter()
+                .filter(|(seq, _)| *seq > cursor)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        (live.subscribe(), backlog, rejection)
+    };
+
+    if let Some(frame) = rejection {
+        let _ = ws.send(Message::Binary(frame.to_vec())).await;
+        let _ = ws.close(None).await;
+        return;
+    }
+
+    let mut last = cursor.unwrap_or(0);
+    for (seq, frame) in backlog {
+        if ws.send(Message::Binary(frame.to_vec())).await.is_err() {
+            return;
+        }
+        last = seq;
+    }
+
+    loop {
+        let sent = match live.recv().await {
+            Ok(Outgoing::Event(seq, _)) if seq <= last => continue,
+            Ok(Outgoing::Event(seq, frame)) => {
+                last = seq;
+                ws.send(Message::Binary(frame.to_vec())).await
+            }
+            Ok(Outgoing::Frame(frame)) => ws.send(Message::Binary(frame.to_vec())).await,
+            Ok(Outgoing::Error(frame)) => {
+                let _ = ws.send(Message::Binary(frame.to_vec())).await;
+                break;
+            }
+            Ok(Outgoing::Close) | Err(broadcast::error::RecvError::Closed) => break,
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                let frame = error_frame("ConsumerTooSlow", Some("Stream consumer too slow"))
+                    .expect("error frame encodes");
+                let _ = ws.send(Message::Binary(frame)).await;
+                break;
+            }
+        };
+        if sent.is_err() {
+            return;
+        }
+    }
+    let _ = ws.close(None).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        stream::EventStream,
+        types::{commit::Record, event::Event},
+        Error,
+    };
+    use futures::StreamExt;
+
+    fn repo() -> Did {
+        "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn mock_relay_end_to_end() {
+        let relay = MockRelay::start().await.unwrap();
+        let post = CommitBuilder::new(repo())
+            .post("3l6oveex3ii2l", "Hello, world!")
+            .build();
+        let post_cid = post.ops[0].cid.clone().unwrap().0;
+        assert_eq!(relay.push_commit(post).unwrap(), 1);
+        let like = CommitBuilder::new(repo())
+            .like(
+                "3l6oveex3ii2m",
+                "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l",
+                post_cid,
+            )
+            .delete("app.bsky.feed.post", "3l6oveex3ii2l")
+            .build();
+        assert_eq!(relay.push_commit(like).unwrap(), 2);
+
+        // A cursor replays the backlog, then live events follow
+        let subscription = relay.subscription().cursor(0).connect().await.unwrap();
+        let mut binding = EventStream::new(subscription);
+        let mut records = std::pin::pin!(binding.stream().await.unwrap());
+        assert!(matches!(records.next().await, Some(Record::Post(_))));
+        assert!(matches!(records.next().await, Some(Record::Like(_))));
+        assert!(matches!(records.next().await, Some(Record::Delete(_))));
+        relay
+            .push_commit(
+                CommitBuilder::new(repo())
+                    .follow(
+                        "3l6oveex3ii2n",
+                        "did:plc:ewvi7nxzyoun6zhxrhs64oiz".parse().unwrap(),
+                    )
+                    .build(),
+            )
+            .unwrap();
+        assert!(matches!(records.next().await, Some(Record::Follow(_))));
+
+        // A cursor past the last event is refused
+        let mut subscription = relay.subscription().cursor(10).connect().await.unwrap();
+        let result = subscription.next_event().await;
+        assert!(
+            matches!(result, Some(Err(Error::FutureCursor(_)))),
+            "{result:?}"
+        );
+
+        // Error frames end the connection
+        let mut subscription = relay.subscription().connect().await.unwrap();
+        relay.wait_for_connections(3).await;
+        relay
+            .send_error("ConsumerTooSlow", Some("Stream consumer too slow"))
+            .unwrap();
+        let result = subscription.next_event().await;
+        assert!(
+            matches!(result, Some(Err(Error::ConsumerTooSlow(_)))),
+            "{result:?}"
+        );
+        assert!(subscription.next_event().await.is_none());
+        assert_eq!(relay.connections(), 3);
+
+        // Commits from the relay decode like the real thing
+        let mut subscription = relay.subscription().cursor(3).connect().await.unwrap();
+        relay
+            .push_commit(CommitBuilder::new(repo()).build())
+            .unwrap();
+        let event = subscription.next_event().await;
+        assert!(
+            matches!(event, Some(Ok(Event::Commit(ref commit))) if commit.inner().seq == 4),
+            "{event:?}"
+        );
+    }
+}
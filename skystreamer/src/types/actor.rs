@@ -2,13 +2,13 @@
 use crate::util::{conv_atrium_cid, datetime_to_chrono};
 use atrium_api::{
     app::bsky::{self},
-    types::{string::Did, CidLink, Union},
+    types::{string::Did, CidLink, Collection, Union},
 };
 use chrono::{DateTime, FixedOffset};
 use cid::Cid;
 use serde::{Deserialize, Serialize};
 
-use super::Blob;
+use super::{operation::Action, AtUri, Blob};
 
 /// A user profile
 ///
@@ -20,6 +20,8 @@ use super::Blob;
 pub struct Profile {
     /// The ATProto DID (Repo ID) of the user
     pub did: Did,
+    /// The AT-URI of the profile record
+    pub uri: AtUri,
     /// Link to the user's profile image, if any
     pub avatar: Option<Blob>,
     /// The date the profile was created
@@ -33,18 +35,26 @@ pub struct Profile {
     pub labels: Vec<String>,
     /// A link to the user's pinned post on their profile,
     /// Refers to [`crate::types::Post`]
+    #[serde(default, with = "crate::util::cid_string::option")]
     pub pinned_post: Option<Cid>,
+    /// The CID of the profile record
+    #[serde(default)]
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
 }
 
 impl Profile {
     /// Create a new profile from their record stream.
     /// Used in the [`crate::stream::EventStream`] to create a profile from a commit.
-    pub fn new(did: Did, record: bsky::actor::profile::Record, cid: Option<CidLink>) -> Self {
-        if let Some(cid) = cid {
-            tracing::trace!("Profile cid: {:?}", cid);
-        }
-
+    pub fn new(
+        did: Did,
+        record: bsky::actor::profile::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         Self {
+            uri: AtUri::new(did.clone(), bsky::actor::Profile::NSID, rkey),
             did,
             avatar: record.avatar.clone().map(Blob::from),
             created_at: record.created_at.as_ref().map(datetime_to_chrono),
@@ -63,6 +73,8 @@ impl Profile {
                 })
                 .unwrap_or_default(),
             pinned_post: record.pinned_post.as_ref().map(|p| conv_atrium_cid(&p.cid)),
+            cid,
+            action: Action::Create,
         }
     }
 }
@@ -0,0 +1,103 @@
+//! AT-URIs, the `at://` links that address records in a repository.
+use crate::Error;
+use atrium_api::types::string::Did;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, str::FromStr};
+
+/// An AT-URI pointing to a single record, in the form of `at://{did}/{collection}/{rkey}`.
+///
+/// Serializes to and from its string form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AtUri {
+    /// The DID of the repository the record lives in
+    pub did: Did,
+    /// The collection NSID of the record, e.g. `app.bsky.feed.post`
+    pub collection: String,
+    /// The record key, unique within the collection
+    pub rkey: String,
+}
+
+impl AtUri {
+    pub fn new(did: Did, collection: impl Into<String>, rkey: impl Into<String>) -> Self {
+        Self {
+            did,
+            collection: collection.into(),
+            rkey: rkey.into(),
+        }
+    }
+}
+
+impl fmt::Display for AtUri {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "at://{}/{}/{}",
+            self.did.as_str(),
+            self.collection,
+            self.rkey
+        )
+    }
+}
+
+impl FromStr for AtUri {
+    type Err = Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || Error::InvalidAtUri(s.to_string());
+        let path = s.strip_prefix("at://").ok_or_else(invalid)?;
+        let mut parts = path.splitn(3, '/');
+        let (Some(did), Some(collection), Some(rkey)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        if collection.is_empty() || rkey.is_empty() || rkey.contains('/') {
+            return Err(invalid());
+        }
+        Ok(Self {
+            did: did.parse().map_err(|_| invalid())?,
+            collection: collection.to_string(),
+            rkey: rkey.to_string(),
+        })
+    }
+}
+
+impl Serialize for AtUri {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for AtUri {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let s = "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l";
+        let uri: AtUri = s.parse().expect("failed to parse AT-URI");
+        assert_eq!(uri.did.as_str(), "did:plc:z72i7hdynmk6r22z27h6tvur");
+        assert_eq!(uri.collection, "app.bsky.feed.post");
+        assert_eq!(uri.rkey, "3l6oveex3ii2l");
+        assert_eq!(uri.to_string(), s);
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for s in [
+            "https://bsky.app/profile/bsky.app",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post",
+            "at://bsky.app/app.bsky.feed.post/3l6oveex3ii2l",
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/a/b",
+        ] {
+            assert!(s.parse::<AtUri>().is_err(), "{s} should not parse");
+        }
+    }
+}
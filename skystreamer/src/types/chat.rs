@@ -0,0 +1,64 @@
+//! Helper types for Bluesky chat events.
+//!
+//! Chat messages themselves are not part of the firehose, but the chat settings
+//! of an account are stored in its repository.
+
+use super::{operation::Action, AtUri};
+use atrium_api::{
+    chat::bsky,
+    types::{string::Did, CidLink, Collection},
+};
+use serde::{Deserialize, Serialize};
+
+/// Who can start a chat with an account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AllowIncoming {
+    /// Anyone can start a chat
+    All,
+    /// Nobody can start a chat
+    None,
+    /// Only accounts the user follows can start a chat
+    Following,
+    /// A setting not (yet) known to the library
+    Other(String),
+}
+
+impl From<&str> for AllowIncoming {
+    fn from(value: &str) -> Self {
+        match value {
+            "all" => AllowIncoming::All,
+            "none" => AllowIncoming::None,
+            "following" => AllowIncoming::Following,
+            other => AllowIncoming::Other(other.to_string()),
+        }
+    }
+}
+
+/// An event where someone declares their chat settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatDeclarationEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    pub allow_incoming: AllowIncoming,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl ChatDeclarationEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::actor::declaration::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        Self {
+            uri: AtUri::new(author.clone(), bsky::actor::Declaration::NSID, rkey),
+            author,
+            allow_incoming: record.allow_incoming.as_str().into(),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
@@ -1,14 +1,66 @@
 //! Helper types for deserialize commit data from the firehose.
 
-use super::{actor::Profile, feed::*, graph::*, operation::Operation, Post};
-use crate::{Error, Result};
+use super::{
+    actor::Profile,
+    chat::ChatDeclarationEvent,
+    custom::{CustomRecord, DecoderRegistry},
+    feed::*,
+    graph::*,
+    ipld::DagJson,
+    labeler::LabelerServiceEvent,
+    operation::{Action, Operation},
+    AtUri, Post,
+};
+use crate::{
+    util::{datetime_to_chrono, WithoutFields},
+    verify::CommitVerifier,
+    Error, Result,
+};
 use atrium_api::{
-    app::bsky, com::atproto::sync::subscribe_repos::Commit as ACommit, types::CidLink,
+    app::bsky,
+    com::atproto::sync::subscribe_repos::Commit as ACommit,
+    types::{string::Did, CidLink},
 };
-use std::convert::From;
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
+use cid::Cid;
+use ipld_core::ipld::Ipld;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, convert::From, sync::Arc};
+
+/// The version of the JSON schema [`Record`]s serialize to.
+///
+/// Bumped whenever a field of the envelope changes or is removed.
+pub const RECORD_SCHEMA_VERSION: u32 = 1;
 
 /// A record is an event that happens on ATProto.
 /// It can be a post, or any kind of new event emitted from the network itself.
+///
+/// # Serialization
+///
+/// Records serialize to an object tagged by the kind of record, with the fields
+/// shared by all kinds in the same place, regardless of what the Rust type calls them:
+///
+/// ```json
+/// {
+///   "schema_version": 1,
+///   "type": "like",
+///   "action": "create",
+///   "author": "did:plc:z72i7hdynmk6r22z27h6tvur",
+///   "uri": "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.like/3l6oveex3ii2l",
+///   "collection": "app.bsky.feed.like",
+///   "cid": "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm",
+///   "created_at": "2024-10-17T12:00:00Z",
+///   "record": { "subject": { "uri": "at://...", "cid": "..." } }
+/// }
+/// ```
+///
+/// - `schema_version` is [`RECORD_SCHEMA_VERSION`].
+/// - `type` is [`Record::kind`].
+/// - `cid` and `created_at` are `null` if the kind of record has none, e.g. for deletes.
+/// - `record` holds the remaining fields of the record's type.
+///   For [`Record::Other`] it is the record's DAG-JSON.
+///   For [`Record::Custom`] it is `null`, as custom record data is not serializable.
 #[derive(Debug, Clone)]
 pub enum Record {
     /// A new post
@@ -25,111 +77,672 @@ pub enum Record {
     ListItem(Box<ListItemEvent>),
     /// A new user being created on the network
     Profile(Box<Profile>),
+    /// Someone publishes a custom feed generator
+    FeedGenerator(Box<FeedGeneratorEvent>),
+    /// Someone restricts who can reply to their post
+    Threadgate(Box<ThreadgateEvent>),
+    /// Someone restricts how their post can be quoted
+    Postgate(Box<PostgateEvent>),
+    /// A new list of users
+    List(Box<ListEvent>),
+    /// A user blocks every user on a list
+    ListBlock(Box<ListBlockEvent>),
+    /// A new starter pack
+    StarterPack(Box<StarterPackEvent>),
+    /// Someone declares their account as a labeler
+    LabelerService(Box<LabelerServiceEvent>),
+    /// Someone declares their chat settings
+    ChatDeclaration(Box<ChatDeclarationEvent>),
+    /// A record decoded by a user-defined [`crate::types::custom::RecordDecoder`]
+    Custom(Box<CustomRecord>),
+    /// A record of any kind being deleted,
+    /// e.g. an unlike, unfollow, unblock or post deletion
+    Delete(Box<DeleteEvent>),
     // Other(Box<serde::de::value::>),
     /// Other, (yet) unsupported record types
     ///
     /// This is a catch-all for any record type that is not yet supported by the library.
     ///
-    /// Returns a serde_json::Value, which can be used to inspect the raw data or manually
-    /// deserialize it if needed.
-    Other((Operation, Box<serde_json::Value>)),
+    /// Carries the raw [`Ipld`] tree of the record, which keeps byte strings and CID links
+    /// intact. Use [`crate::types::ipld`] to convert it to DAG-JSON or deserialize it
+    /// into a typed value.
+    Other(Box<OtherRecord>),
+}
+
+/// A record of a collection not (yet) supported by the library
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtherRecord {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The operation that produced this record
+    pub operation: Operation,
+    /// The raw data of the record
+    pub data: Ipld,
+}
+
+/// An event where a record is deleted from a repository.
+///
+/// Deleted records carry no data, so only the collection and record key
+/// of the deleted record are known.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeleteEvent {
+    pub author: Did,
+    /// The AT-URI of the deleted record
+    pub uri: AtUri,
+    /// The collection NSID of the deleted record, e.g. `app.bsky.feed.like`
+    pub collection: String,
+    /// The record key of the deleted record
+    pub rkey: String,
 }
 
 impl Record {
     /// Deserialize an operation into the Record enum, given a commit.
     ///
     /// Returns all the records that can be extracted from the operation.
+    ///
+    /// This decodes the whole CAR slice of the commit on every call. When extracting
+    /// multiple operations from the same commit, use [`Commit::extract_records`] or
+    /// [`Record::from_op_with_blocks`] instead.
     pub async fn from_op(op: &Operation, commit: &ACommit) -> Result<Vec<Self>> {
-        let mut blocks = commit.blocks.as_slice();
+        let blocks = BlockMap::from_car(&commit.blocks).await?;
+        Self::from_op_with_blocks(op, commit, &blocks)
+    }
+
+    /// Deserialize an operation into the Record enum, looking up its data in
+    /// the already decoded blocks of the commit.
+    pub fn from_op_with_blocks(
+        op: &Operation,
+        commit: &ACommit,
+        blocks: &BlockMap,
+    ) -> Result<Vec<Self>> {
+        Self::from_op_with_decoders(op, commit, blocks, &DecoderRegistry::default())
+    }
+
+    /// Deserialize an operation into the Record enum, like [`Record::from_op_with_blocks`],
+    /// decoding collections registered in `decoders` into [`Record::Custom`].
+    pub fn from_op_with_decoders(
+        op: &Operation,
+        commit: &ACommit,
+        blocks: &BlockMap,
+        decoders: &DecoderRegistry,
+    ) -> Result<Vec<Self>> {
         let mut records = vec![];
-        let (items, _) = rs_car::car_read_all(&mut blocks, true).await?;
-        let (_item_cid, item) = items
-            .iter()
-            .find(|(cid, _)| {
-                let converted_cid = CidLink(
-                    crate::types::CidOld::from(*cid)
-                        .try_into()
-                        .expect("invalid CID conversion"),
-                );
-                Some(converted_cid) == op.get_cid()
-            })
-            .ok_or_else(|| Error::ItemNotFound(op.get_cid(), items.len()))?;
+        let action = op.action();
+
+        // Deletions don't have a record block to decode
+        if action == Action::Delete {
+            records.push(Record::Delete(Box::new(DeleteEvent {
+                author: commit.repo.clone(),
+                uri: op.uri(&commit.repo),
+                collection: op.collection().to_string(),
+                rkey: op.rkey().to_string(),
+            })));
+            return Ok(records);
+        }
+
+        let item = op
+            .get_cid()
+            .and_then(|cid| blocks.get(&cid.0))
+            .ok_or_else(|| Error::ItemNotFound(op.get_cid(), blocks.len()))?;
+
+        if let Some(decoder) = decoders.get(op.collection()) {
+            records.push(Record::Custom(Box::new(CustomRecord::new(
+                commit.repo.clone(),
+                op.uri(&commit.repo),
+                op.get_cid(),
+                action,
+                decoder.decode(item)?,
+            ))));
+            return Ok(records);
+        }
+
         match op {
             Operation::Post(cidlink, _) => {
-                records.push(Record::Post(Box::new(Post::from_record(
-                    commit.repo.clone(),
-                    cidlink.clone().unwrap(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                ))));
+                records.push(Record::Post(Box::new(Post {
+                    action,
+                    ..Post::from_record(
+                        commit.repo.clone(),
+                        cidlink.clone().unwrap(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        op.rkey(),
+                    )
+                })));
             }
             Operation::Block(a, _) => {
-                records.push(Record::Block(Box::new(BlockEvent::new(
-                    commit.repo.clone(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                    a.clone(),
-                ))));
+                records.push(Record::Block(Box::new(BlockEvent {
+                    action,
+                    ..BlockEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        a.clone(),
+                        op.rkey(),
+                    )
+                })));
             }
             Operation::Like(link, _) => {
-                records.push(Record::Like(Box::new(LikeEvent::new(
-                    commit.repo.clone(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                    link.clone(),
-                ))));
+                records.push(Record::Like(Box::new(LikeEvent {
+                    action,
+                    ..LikeEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
             }
             Operation::Follow(link, _) => {
                 // let follow: bsky::graph::follow::Record =
-                //     serde_ipld_dagcbor::from_reader(&mut item.as_slice())?;
+                //     serde_ipld_dagcbor::from_reader(item)?;
 
-                records.push(Record::Follow(Box::new(FollowEvent::new(
-                    commit.repo.clone(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                    link.clone(),
-                ))));
+                records.push(Record::Follow(Box::new(FollowEvent {
+                    action,
+                    ..FollowEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
             }
 
             Operation::Repost(link, _) => {
-                let repost: bsky::feed::repost::Record =
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?;
-                records.push(Record::Repost(Box::new(RepostEvent::new(
-                    commit.repo.clone(),
-                    repost,
-                    link.clone(),
-                ))));
+                let repost: bsky::feed::repost::Record = serde_ipld_dagcbor::from_reader(item)?;
+                records.push(Record::Repost(Box::new(RepostEvent {
+                    action,
+                    ..RepostEvent::new(commit.repo.clone(), repost, link.clone(), op.rkey())
+                })));
             }
 
             Operation::ListItem(link, _) => {
-                records.push(Record::ListItem(Box::new(ListItemEvent::new(
-                    commit.repo.clone(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                    link.clone(),
-                ))));
+                records.push(Record::ListItem(Box::new(ListItemEvent {
+                    action,
+                    ..ListItemEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
             }
 
             Operation::Profile(link, _) => {
                 // todo
 
-                let profile: bsky::actor::profile::Record =
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?;
+                let profile: bsky::actor::profile::Record = serde_ipld_dagcbor::from_reader(item)?;
+
+                records.push(Record::Profile(Box::new(Profile {
+                    action,
+                    ..Profile::new(commit.repo.clone(), profile, link.clone(), op.rkey())
+                })));
+            }
+
+            Operation::FeedGenerator(link, _) => {
+                records.push(Record::FeedGenerator(Box::new(FeedGeneratorEvent {
+                    action,
+                    ..FeedGeneratorEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::Threadgate(link, _) => {
+                records.push(Record::Threadgate(Box::new(ThreadgateEvent {
+                    action,
+                    ..ThreadgateEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::Postgate(link, _) => {
+                records.push(Record::Postgate(Box::new(PostgateEvent {
+                    action,
+                    ..PostgateEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::List(link, _) => {
+                records.push(Record::List(Box::new(ListEvent {
+                    action,
+                    ..ListEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
 
-                records.push(Record::Profile(Box::new(Profile::new(
-                    commit.repo.clone(),
-                    profile,
-                    link.clone(),
-                ))));
+            Operation::ListBlock(link, _) => {
+                records.push(Record::ListBlock(Box::new(ListBlockEvent {
+                    action,
+                    ..ListBlockEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::StarterPack(link, _) => {
+                records.push(Record::StarterPack(Box::new(StarterPackEvent {
+                    action,
+                    ..StarterPackEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::LabelerService(link, _) => {
+                records.push(Record::LabelerService(Box::new(LabelerServiceEvent {
+                    action,
+                    ..LabelerServiceEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
+            }
+
+            Operation::ChatDeclaration(link, _) => {
+                records.push(Record::ChatDeclaration(Box::new(ChatDeclarationEvent {
+                    action,
+                    ..ChatDeclarationEvent::new(
+                        commit.repo.clone(),
+                        serde_ipld_dagcbor::from_reader(item)?,
+                        link.clone(),
+                        op.rkey(),
+                    )
+                })));
             }
 
             other => {
                 tracing::trace!("Unhandled operation: {:?}", other);
                 // todo: some kind of generic Serde value?
-                records.push(Record::Other(*Box::new((
-                    other.clone(),
-                    serde_ipld_dagcbor::from_reader(&mut item.as_slice())?,
-                ))));
+                records.push(Record::Other(Box::new(OtherRecord {
+                    author: commit.repo.clone(),
+                    uri: op.uri(&commit.repo),
+                    operation: other.clone(),
+                    data: serde_ipld_dagcbor::from_reader(item)?,
+                })));
             }
         }
 
         Ok(records)
     }
+
+    /// Get the action that produced this record, i.e. whether it was created, updated or deleted
+    pub fn action(&self) -> Action {
+        match self {
+            Record::Post(post) => post.action,
+            Record::Block(block) => block.action,
+            Record::Like(like) => like.action,
+            Record::Follow(follow) => follow.action,
+            Record::Repost(repost) => repost.action,
+            Record::ListItem(item) => item.action,
+            Record::Profile(profile) => profile.action,
+            Record::FeedGenerator(generator) => generator.action,
+            Record::Threadgate(gate) => gate.action,
+            Record::Postgate(gate) => gate.action,
+            Record::List(list) => list.action,
+            Record::ListBlock(block) => block.action,
+            Record::StarterPack(pack) => pack.action,
+            Record::LabelerService(service) => service.action,
+            Record::ChatDeclaration(declaration) => declaration.action,
+            Record::Custom(custom) => custom.action,
+            Record::Delete(_) => Action::Delete,
+            Record::Other(other) => other.operation.action(),
+        }
+    }
+
+    /// The kind of record, as used for the `type` tag of its serialized form
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Record::Post(_) => "post",
+            Record::Block(_) => "block",
+            Record::Like(_) => "like",
+            Record::Follow(_) => "follow",
+            Record::Repost(_) => "repost",
+            Record::ListItem(_) => "list_item",
+            Record::Profile(_) => "profile",
+            Record::FeedGenerator(_) => "feed_generator",
+            Record::Threadgate(_) => "threadgate",
+            Record::Postgate(_) => "postgate",
+            Record::List(_) => "list",
+            Record::ListBlock(_) => "list_block",
+            Record::StarterPack(_) => "starter_pack",
+            Record::LabelerService(_) => "labeler_service",
+            Record::ChatDeclaration(_) => "chat_declaration",
+            Record::Custom(_) => "custom",
+            Record::Delete(_) => "delete",
+            Record::Other(_) => "other",
+        }
+    }
+
+    /// The DID of the repository the record belongs to
+    pub fn author(&self) -> &Did {
+        match self {
+            Record::Post(post) => &post.author,
+            Record::Block(block) => &block.author,
+            Record::Like(like) => &like.author,
+            Record::Follow(follow) => &follow.author,
+            Record::Repost(repost) => &repost.author,
+            Record::ListItem(item) => &item.author,
+            Record::Profile(profile) => &profile.did,
+            Record::FeedGenerator(generator) => &generator.author,
+            Record::Threadgate(gate) => &gate.author,
+            Record::Postgate(gate) => &gate.author,
+            Record::List(list) => &list.author,
+            Record::ListBlock(block) => &block.author,
+            Record::StarterPack(pack) => &pack.author,
+            Record::LabelerService(service) => &service.author,
+            Record::ChatDeclaration(declaration) => &declaration.author,
+            Record::Custom(custom) => &custom.author,
+            Record::Delete(delete) => &delete.author,
+            Record::Other(other) => &other.author,
+        }
+    }
+
+    /// The AT-URI of the record
+    pub fn uri(&self) -> &AtUri {
+        match self {
+            Record::Post(post) => &post.uri,
+            Record::Block(block) => &block.uri,
+            Record::Like(like) => &like.uri,
+            Record::Follow(follow) => &follow.uri,
+            Record::Repost(repost) => &repost.uri,
+            Record::ListItem(item) => &item.uri,
+            Record::Profile(profile) => &profile.uri,
+            Record::FeedGenerator(generator) => &generator.uri,
+            Record::Threadgate(gate) => &gate.uri,
+            Record::Postgate(gate) => &gate.uri,
+            Record::List(list) => &list.uri,
+            Record::ListBlock(block) => &block.uri,
+            Record::StarterPack(pack) => &pack.uri,
+            Record::LabelerService(service) => &service.uri,
+            Record::ChatDeclaration(declaration) => &declaration.uri,
+            Record::Custom(custom) => &custom.uri,
+            Record::Delete(delete) => &delete.uri,
+            Record::Other(other) => &other.uri,
+        }
+    }
+
+    /// The collection NSID of the record, e.g. `app.bsky.feed.post`
+    pub fn collection(&self) -> &str {
+        &self.uri().collection
+    }
+
+    /// The CID of the record, if known. Deleted records have none.
+    pub fn cid(&self) -> Option<Cid> {
+        let link = match self {
+            Record::Post(post) => return Some(post.id),
+            Record::Block(block) => &block.cid,
+            Record::Like(like) => &like.cid,
+            Record::Follow(follow) => &follow.cid,
+            Record::Repost(repost) => &repost.cid,
+            Record::ListItem(item) => &item.cid,
+            Record::Profile(profile) => &profile.cid,
+            Record::FeedGenerator(generator) => &generator.cid,
+            Record::Threadgate(gate) => &gate.cid,
+            Record::Postgate(gate) => &gate.cid,
+            Record::List(list) => &list.cid,
+            Record::ListBlock(block) => &block.cid,
+            Record::StarterPack(pack) => &pack.cid,
+            Record::LabelerService(service) => &service.cid,
+            Record::ChatDeclaration(declaration) => &declaration.cid,
+            Record::Custom(custom) => &custom.cid,
+            Record::Delete(_) => return None,
+            Record::Other(other) => return other.operation.get_cid().map(|cid| cid.0),
+        };
+        link.as_ref().map(|cid| cid.0)
+    }
+
+    /// When the record says it was created, if it has a timestamp
+    pub fn created_at(&self) -> Option<DateTime<FixedOffset>> {
+        match self {
+            Record::Post(post) => Some(post.created_at),
+            Record::Block(block) => Some(block.created_at),
+            Record::Like(like) => Some(like.created_at),
+            Record::Follow(follow) => Some(follow.created_at),
+            Record::Repost(repost) => Some(repost.created_at),
+            Record::ListItem(item) => Some(item.created_at),
+            Record::Profile(profile) => profile.created_at,
+            Record::FeedGenerator(generator) => Some(generator.created_at),
+            Record::Threadgate(gate) => Some(gate.created_at),
+            Record::Postgate(gate) => Some(gate.created_at),
+            Record::List(list) => Some(list.created_at),
+            Record::ListBlock(block) => Some(block.created_at),
+            Record::StarterPack(pack) => Some(pack.created_at),
+            Record::LabelerService(service) => Some(service.created_at),
+            Record::ChatDeclaration(_)
+            | Record::Custom(_)
+            | Record::Delete(_)
+            | Record::Other(_) => None,
+        }
+    }
+}
+
+/// The type-specific fields of a record, for the `record` field of its serialized form
+struct RecordData<'a>(&'a Record);
+
+impl Serialize for RecordData<'_> {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        /// The fields hoisted into the envelope
+        const ENVELOPE: &[&str] = &["author", "uri", "cid", "created_at", "action"];
+        const POST: &[&str] = &["author", "uri", "cid", "created_at", "action", "id"];
+        const PROFILE: &[&str] = &["author", "uri", "cid", "created_at", "action", "did"];
+        const DELETE: &[&str] = &["author", "uri", "cid", "created_at", "action", "collection"];
+
+        fn strip<T: Serialize, S: serde::Serializer>(
+            value: &T,
+            skip: &[&str],
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error> {
+            WithoutFields { value, skip }.serialize(serializer)
+        }
+
+        match self.0 {
+            Record::Post(post) => strip(post, POST, serializer),
+            Record::Block(block) => strip(block, ENVELOPE, serializer),
+            Record::Like(like) => strip(like, ENVELOPE, serializer),
+            Record::Follow(follow) => strip(follow, ENVELOPE, serializer),
+            Record::Repost(repost) => strip(repost, ENVELOPE, serializer),
+            Record::ListItem(item) => strip(item, ENVELOPE, serializer),
+            Record::Profile(profile) => strip(profile, PROFILE, serializer),
+            Record::FeedGenerator(generator) => strip(generator, ENVELOPE, serializer),
+            Record::Threadgate(gate) => strip(gate, ENVELOPE, serializer),
+            Record::Postgate(gate) => strip(gate, ENVELOPE, serializer),
+            Record::List(list) => strip(list, ENVELOPE, serializer),
+            Record::ListBlock(block) => strip(block, ENVELOPE, serializer),
+            Record::StarterPack(pack) => strip(pack, ENVELOPE, serializer),
+            Record::LabelerService(service) => strip(service, ENVELOPE, serializer),
+            Record::ChatDeclaration(declaration) => strip(declaration, ENVELOPE, serializer),
+            Record::Custom(_) => serializer.serialize_unit(),
+            Record::Delete(delete) => strip(delete, DELETE, serializer),
+            Record::Other(other) => DagJson(&other.data).serialize(serializer),
+        }
+    }
+}
+
+impl Serialize for Record {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut state = serializer.serialize_struct("Record", 9)?;
+        state.serialize_field("schema_version", &RECORD_SCHEMA_VERSION)?;
+        state.serialize_field("type", self.kind())?;
+        state.serialize_field("action", &self.action())?;
+        state.serialize_field("author", self.author())?;
+        state.serialize_field("uri", self.uri())?;
+        state.serialize_field("collection", self.collection())?;
+        state.serialize_field("cid", &self.cid().map(|cid| cid.to_string()))?;
+        state.serialize_field("created_at", &self.created_at())?;
+        state.serialize_field("record", &RecordData(self))?;
+        state.end()
+    }
+}
+
+/// Metadata of the commit a record was extracted from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitMeta {
+    /// The DID of the repository the commit belongs to
+    pub repo: Did,
+    /// The firehose sequence number of the commit
+    pub seq: i64,
+    /// When the commit was emitted by the relay
+    pub time: DateTime<FixedOffset>,
+    /// The revision of the repository after this commit
+    pub rev: String,
+    /// The revision of the repository this commit is based on, if any
+    pub since: Option<String>,
+    /// Whether the commit was too big for the relay to include all of its blocks
+    pub too_big: bool,
+    /// The CID of the commit object
+    #[serde(with = "crate::util::cid_string")]
+    pub commit: Cid,
+}
+
+impl From<&ACommit> for CommitMeta {
+    fn from(commit: &ACommit) -> Self {
+        Self {
+            repo: commit.repo.clone(),
+            seq: commit.seq,
+            time: datetime_to_chrono(&commit.time),
+            rev: commit.rev.clone(),
+            since: commit.since.clone(),
+            too_big: commit.too_big,
+            commit: commit.commit.0,
+        }
+    }
+}
+
+/// A record, along with the metadata of the commit it was extracted from.
+///
+/// The metadata is shared between all records of the same commit.
+#[derive(Debug, Clone)]
+pub struct RecordEnvelope {
+    pub commit: Arc<CommitMeta>,
+    pub record: Record,
+}
+
+impl RecordEnvelope {
+    /// The time between the relay emitting the commit and now
+    pub fn ingest_lag(&self) -> TimeDelta {
+        Utc::now().fixed_offset() - self.commit.time
+    }
+}
+
+/// Multicodec code of DAG-CBOR, the codec of every block in a repository
+const DAG_CBOR: u64 = 0x71;
+/// Multihash code of SHA2-256, the hash function of every block in a repository
+const SHA2_256: u64 = 0x12;
+
+/// Check that `data` hashes to `cid`, and that `cid` is a SHA2-256 DAG-CBOR CID
+/// as used by AT Protocol repositories.
+pub fn verify_block(cid: &Cid, data: &[u8]) -> Result<()> {
+    verify_block_cid(cid)?;
+    if cid.hash().digest() != Sha256::digest(data).as_slice() {
+        return Err(Error::BlockIntegrity {
+            cid: *cid,
+            reason: "content does not match hash".to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Check that `cid` is a SHA2-256 DAG-CBOR CID, without hashing the block
+fn verify_block_cid(cid: &Cid) -> Result<()> {
+    let integrity_error = |reason: String| Error::BlockIntegrity { cid: *cid, reason };
+    if cid.codec() != DAG_CBOR {
+        return Err(integrity_error(format!(
+            "unexpected codec {:#x}",
+            cid.codec()
+        )));
+    }
+    if cid.hash().code() != SHA2_256 {
+        return Err(integrity_error(format!(
+            "unexpected hash function {:#x}",
+            cid.hash().code()
+        )));
+    }
+    Ok(())
+}
+
+/// The blocks of a commit's CAR slice, keyed by their CID.
+#[derive(Debug, Clone, Default)]
+pub struct BlockMap {
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+impl BlockMap {
+    /// Decode a CAR slice, such as the `blocks` of an [`ACommit`], into a map of blocks.
+    ///
+    /// Every block is hashed by the CAR decoder, failing with [`Error::CarDecoder`] if its
+    /// content does not match its CID. On top of that, every CID must be a SHA2-256
+    /// DAG-CBOR CID like [`verify_block`] requires, failing with [`Error::BlockIntegrity`]
+    /// otherwise: the CAR decoder also accepts other codecs and hash functions, including
+    /// identity hashes, which AT Protocol repositories never use. A CID that cannot be
+    /// represented fails with [`Error::InvalidCid`] instead of panicking.
+    pub async fn from_car(car: &[u8]) -> Result<Self> {
+        Self::from_car_with(car, true).await
+    }
+
+    /// Decode a CAR slice into a map of blocks, only verifying the blocks if `verify` is set.
+    ///
+    /// Skipping verification saves hashing every block, but trusts the relay to send
+    /// blocks that match their CIDs.
+    pub async fn from_car_with(mut car: &[u8], verify: bool) -> Result<Self> {
+        let (items, _) = rs_car::car_read_all(&mut car, verify).await?;
+        let blocks = items
+            .into_iter()
+            .map(|(cid, block)| {
+                let cid = Cid::try_from(crate::types::CidOld::from(cid))?;
+                if verify {
+                    verify_block_cid(&cid)?;
+                }
+                Ok((cid, block))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { blocks })
+    }
+
+    /// Get the data of a block by its CID
+    pub fn get(&self, cid: &Cid) -> Option<&[u8]> {
+        self.blocks.get(cid).map(Vec::as_slice)
+    }
+
+    /// The number of blocks in the map
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether the map contains no blocks
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
 }
 
 /// A singular commit, containing a list of operations.
@@ -139,9 +752,17 @@ impl Record {
 pub struct Commit {
     pub operations: Vec<Operation>,
     inner_commit: ACommit,
+    /// Whether to check the integrity of blocks when extracting records
+    verify_blocks: bool,
 }
 impl From<&ACommit> for Commit {
     fn from(commit: &atrium_api::com::atproto::sync::subscribe_repos::Commit) -> Self {
+        Commit::from(commit.clone())
+    }
+}
+
+impl From<ACommit> for Commit {
+    fn from(commit: ACommit) -> Self {
         let ops = commit
             .ops
             .iter()
@@ -154,7 +775,8 @@ impl From<&ACommit> for Commit {
 
         Commit {
             operations: ops,
-            inner_commit: commit.clone(),
+            inner_commit: commit,
+            verify_blocks: true,
         }
     }
 }
@@ -188,14 +810,376 @@ impl Commit {
         &self.inner_commit
     }
 
+    /// Whether to check that every block of the commit matches its CID before decoding it.
+    ///
+    /// Enabled by default. Disabling it skips hashing every block, for throughput.
+    pub fn with_block_verification(mut self, enabled: bool) -> Self {
+        self.verify_blocks = enabled;
+        self
+    }
+
+    /// Get the metadata of the commit
+    pub fn meta(&self) -> CommitMeta {
+        CommitMeta::from(&self.inner_commit)
+    }
+
+    /// Consume the commit, returning the raw commit data.
+    pub fn into_inner(self) -> ACommit {
+        self.inner_commit
+    }
+
     /// Extracts all records from the commit.
+    ///
+    /// The CAR slice of the commit is only decoded once, no matter how many
+    /// operations the commit contains.
+    ///
+    /// Records that fail to decode are skipped, see [`Commit::try_extract_records`]
+    /// to handle them instead.
     pub async fn extract_records(&self) -> Vec<Record> {
+        self.extract_records_with(&DecoderRegistry::default()).await
+    }
+
+    /// Extracts all records from the commit, like [`Commit::extract_records`],
+    /// decoding collections registered in `decoders` into [`Record::Custom`].
+    pub async fn extract_records_with(&self, decoders: &DecoderRegistry) -> Vec<Record> {
+        self.try_extract_records_with(decoders)
+            .await
+            .into_iter()
+            .filter_map(|record| {
+                record
+                    .inspect_err(|e| tracing::debug!("Skipping record: {}", e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Extracts all records from the commit, keeping the errors of records that
+    /// fail to decode.
+    ///
+    /// Errors are wrapped in [`Error::Record`], or [`Error::Commit`] if the
+    /// commit's blocks could not be decoded at all.
+    pub async fn try_extract_records(&self) -> Vec<Result<Record>> {
+        self.try_extract_records_with(&DecoderRegistry::default())
+            .await
+    }
+
+    /// Extracts all records from the commit, like [`Commit::try_extract_records`],
+    /// decoding collections registered in `decoders` into [`Record::Custom`].
+    pub async fn try_extract_records_with(
+        &self,
+        decoders: &DecoderRegistry,
+    ) -> Vec<Result<Record>> {
+        self.extract(decoders, None).await
+    }
+
+    /// Extracts all records from the commit, like [`Commit::try_extract_records_with`],
+    /// but only after verifying the commit with `verifier`.
+    ///
+    /// If verification fails, no records are extracted and the failure is returned
+    /// as the only item, wrapped in [`Error::Commit`].
+    pub async fn try_extract_records_verified(
+        &self,
+        decoders: &DecoderRegistry,
+        verifier: &CommitVerifier,
+    ) -> Vec<Result<Record>> {
+        self.extract(decoders, Some(verifier)).await
+    }
+
+    /// Verify the commit's signature and MST proofs with `verifier`
+    pub async fn verify(&self, verifier: &CommitVerifier) -> Result<()> {
+        verifier.verify(&self.inner_commit).await
+    }
+
+    async fn extract(
+        &self,
+        decoders: &DecoderRegistry,
+        verifier: Option<&CommitVerifier>,
+    ) -> Vec<Result<Record>> {
+        let commit = &self.inner_commit;
         let mut records = vec![];
 
+        // Deletes carry no blocks, so they are still extracted if the CAR slice is
+        // missing (e.g. in `tooBig` commits) or malformed.
+        let (blocks, blocks_ok) = if commit.blocks.is_empty() {
+            (BlockMap::default(), true)
+        } else {
+            match BlockMap::from_car_with(&commit.blocks, self.verify_blocks).await {
+                Ok(blocks) => (blocks, true),
+                Err(e) => {
+                    records.push(Err(Error::Commit {
+                        repo: commit.repo.clone(),
+                        seq: commit.seq,
+                        source: Box::new(e),
+                    }));
+                    (BlockMap::default(), false)
+                }
+            }
+        };
+
+        // Nothing of an unverifiable commit can be trusted, not even its deletes
+        if let Some(verifier) = verifier {
+            if !blocks_ok {
+                return records;
+            }
+            if let Err(e) = verifier.verify_blocks(commit, &blocks).await {
+                return vec![Err(Error::Commit {
+                    repo: commit.repo.clone(),
+                    seq: commit.seq,
+                    source: Box::new(e),
+                })];
+            }
+        }
+
         for op in &self.operations {
-            let new_records = Record::from_op(op, &self.inner_commit).await;
-            records.extend(new_records.unwrap_or_default());
+            if !blocks_ok && op.action() != Action::Delete {
+                continue;
+            }
+            match Record::from_op_with_decoders(op, commit, &blocks, decoders) {
+                Ok(new_records) => records.extend(new_records.into_iter().map(Ok)),
+                Err(e) => {
+                    let op = op.get_op();
+                    records.push(Err(Error::Record {
+                        repo: commit.repo.clone(),
+                        path: op.path.clone(),
+                        cid: op.cid.as_ref().map(|cid| cid.0.to_string()),
+                        source: Box::new(e),
+                    }));
+                }
+            }
         }
         records
     }
+
+    /// Extracts all records from the commit, wrapping each of them with the commit's metadata.
+    pub async fn extract_envelopes(&self) -> Vec<RecordEnvelope> {
+        self.extract_envelopes_with(&DecoderRegistry::default())
+            .await
+    }
+
+    /// Extracts all records from the commit with their commit's metadata, like
+    /// [`Commit::extract_envelopes`], decoding collections registered in `decoders`
+    /// into [`Record::Custom`].
+    pub async fn extract_envelopes_with(&self, decoders: &DecoderRegistry) -> Vec<RecordEnvelope> {
+        let meta = Arc::new(self.meta());
+        self.extract_records_with(decoders)
+            .await
+            .into_iter()
+            .map(|record| RecordEnvelope {
+                commit: meta.clone(),
+                record,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::com::atproto::sync::subscribe_repos::{CommitData, RepoOpData};
+    use atrium_api::types::string::Datetime;
+
+    fn commit_with_ops(ops: Vec<RepoOpData>) -> ACommit {
+        CommitData {
+            blobs: vec![],
+            blocks: vec![],
+            commit: CidLink(
+                "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+                    .parse()
+                    .unwrap(),
+            ),
+            ops: ops.into_iter().map(Into::into).collect(),
+            prev: None,
+            rebase: false,
+            repo: "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            rev: "3l6oveex3ii2l".to_string(),
+            seq: 1,
+            since: None,
+            time: Datetime::now(),
+            too_big: false,
+        }
+        .into()
+    }
+
+    #[test]
+    fn delete_operations_become_delete_records() {
+        let commit = Commit::from(commit_with_ops(vec![RepoOpData {
+            action: "delete".to_string(),
+            cid: None,
+            path: "app.bsky.graph.follow/3l6oveex3ii2l".to_string(),
+        }]));
+        let records = futures::executor::block_on(commit.extract_records());
+
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].action(), Action::Delete);
+        let Record::Delete(delete) = &records[0] else {
+            panic!("expected a delete record, got {:?}", records[0]);
+        };
+        assert_eq!(delete.author.as_str(), "did:plc:z72i7hdynmk6r22z27h6tvur");
+        assert_eq!(delete.collection, "app.bsky.graph.follow");
+        assert_eq!(delete.rkey, "3l6oveex3ii2l");
+        assert_eq!(
+            delete.uri.to_string(),
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.graph.follow/3l6oveex3ii2l"
+        );
+    }
+
+    #[test]
+    fn envelopes_carry_commit_metadata() {
+        let commit = Commit::from(commit_with_ops(vec![RepoOpData {
+            action: "delete".to_string(),
+            cid: None,
+            path: "app.bsky.graph.follow/3l6oveex3ii2l".to_string(),
+        }]));
+        let envelopes = futures::executor::block_on(commit.extract_envelopes());
+
+        assert_eq!(envelopes.len(), 1);
+        assert_eq!(envelopes[0].commit.seq, 1);
+        assert_eq!(envelopes[0].commit.rev, "3l6oveex3ii2l");
+        assert_eq!(
+            envelopes[0].commit.repo.as_str(),
+            "did:plc:z72i7hdynmk6r22z27h6tvur"
+        );
+        assert_eq!(envelopes[0].record.author(), &envelopes[0].commit.repo);
+        assert!(envelopes[0].ingest_lag() >= TimeDelta::zero());
+    }
+
+    #[test]
+    fn missing_blocks_are_reported() {
+        let cid: CidLink = CidLink(
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+                .parse()
+                .unwrap(),
+        );
+        let commit = Commit::from(commit_with_ops(vec![RepoOpData {
+            action: "create".to_string(),
+            cid: Some(cid.clone()),
+            path: "app.bsky.feed.post/3l6oveex3ii2l".to_string(),
+        }]));
+
+        assert!(futures::executor::block_on(commit.extract_records()).is_empty());
+
+        let records = futures::executor::block_on(commit.try_extract_records());
+        assert_eq!(records.len(), 1);
+        let Err(Error::Record {
+            repo,
+            path,
+            cid: error_cid,
+            source,
+        }) = &records[0]
+        else {
+            panic!("expected a record error, got {:?}", records[0]);
+        };
+        assert_eq!(repo.as_str(), "did:plc:z72i7hdynmk6r22z27h6tvur");
+        assert_eq!(path, "app.bsky.feed.post/3l6oveex3ii2l");
+        assert_eq!(error_cid.as_deref(), Some(cid.0.to_string().as_str()));
+        assert!(matches!(**source, Error::ItemNotFound(..)));
+    }
+
+    #[test]
+    fn corrupted_blocks_are_rejected() {
+        use crate::testing::{car, cid_for};
+
+        let block = serde_ipld_dagcbor::to_vec(&Ipld::String("hello".to_string())).unwrap();
+        let cid = cid_for(&block);
+        verify_block(&cid, &block).unwrap();
+
+        let tampered = serde_ipld_dagcbor::to_vec(&Ipld::String("hellp".to_string())).unwrap();
+        assert!(matches!(
+            verify_block(&cid, &tampered),
+            Err(Error::BlockIntegrity { .. })
+        ));
+        let raw = Cid::new_v1(0x55, *cid.hash());
+        assert!(matches!(
+            verify_block(&raw, &block),
+            Err(Error::BlockIntegrity { .. })
+        ));
+
+        // The CAR decoder rejects tampered blocks, unless verification is disabled
+        let tampered_car = car(cid, &[(cid, tampered.clone())]);
+        let result = futures::executor::block_on(BlockMap::from_car(&tampered_car));
+        assert!(matches!(
+            result,
+            Err(Error::CarDecoder(
+                rs_car::CarDecodeError::BlockDigestMismatch(_)
+            ))
+        ));
+        let blocks =
+            futures::executor::block_on(BlockMap::from_car_with(&tampered_car, false)).unwrap();
+        assert_eq!(blocks.get(&cid), Some(tampered.as_slice()));
+
+        // Blocks the CAR decoder accepts, but a repository never contains, are rejected too
+        let raw_car = car(raw, &[(raw, block.clone())]);
+        let result = futures::executor::block_on(BlockMap::from_car(&raw_car));
+        assert!(matches!(result, Err(Error::BlockIntegrity { .. })));
+        let blocks = futures::executor::block_on(BlockMap::from_car_with(&raw_car, false)).unwrap();
+        assert_eq!(blocks.get(&raw), Some(block.as_slice()));
+    }
+
+    #[test]
+    fn record_json_schema() {
+        let repo: Did = "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap();
+        let cid: Cid = "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+            .parse()
+            .unwrap();
+        let like = Record::Like(Box::new(LikeEvent {
+            author: repo.clone(),
+            uri: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.like/3l6oveex3ii2l"
+                .parse()
+                .unwrap(),
+            subject: crate::types::StrongRef {
+                uri: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2k"
+                    .to_string(),
+                cid,
+            },
+            created_at: "2024-10-17T12:00:00Z".parse().unwrap(),
+            cid: Some(CidLink(cid)),
+            action: Action::Create,
+        }));
+        let json = serde_json::to_value(&like).unwrap();
+        assert_eq!(json["schema_version"], RECORD_SCHEMA_VERSION);
+        assert_eq!(json["type"], "like");
+        assert_eq!(json["action"], "create");
+        assert_eq!(json["author"], "did:plc:z72i7hdynmk6r22z27h6tvur");
+        assert_eq!(json["collection"], "app.bsky.feed.like");
+        assert_eq!(json["cid"], cid.to_string());
+        assert_eq!(json["created_at"], "2024-10-17T12:00:00Z");
+        let fields = json["record"].as_object().unwrap();
+        assert_eq!(fields["subject"]["cid"], cid.to_string());
+        assert!(!fields.contains_key("author") && !fields.contains_key("cid"));
+
+        // Binary formats write the number of fields up front, which must leave out the
+        // stripped ones
+        let cbor: Ipld =
+            serde_ipld_dagcbor::from_slice(&serde_ipld_dagcbor::to_vec(&like).unwrap()).unwrap();
+        let Ipld::Map(fields) = cbor.get("record").unwrap().unwrap() else {
+            panic!("record is not a map");
+        };
+        assert_eq!(fields.keys().collect::<Vec<_>>(), ["subject"]);
+
+        let other = Record::Other(Box::new(OtherRecord {
+            author: repo.clone(),
+            uri: "at://did:plc:z72i7hdynmk6r22z27h6tvur/com.example.record/abc"
+                .parse()
+                .unwrap(),
+            operation: Operation::from_op(
+                RepoOpData {
+                    action: "update".to_string(),
+                    cid: Some(CidLink(cid)),
+                    path: "com.example.record/abc".to_string(),
+                }
+                .into(),
+            ),
+            data: Ipld::Map(
+                [("link".to_string(), Ipld::Link(cid))]
+                    .into_iter()
+                    .collect(),
+            ),
+        }));
+        let json = serde_json::to_value(&other).unwrap();
+        assert_eq!(json["type"], "other");
+        assert_eq!(json["action"], "update");
+        assert_eq!(json["cid"], cid.to_string());
+        assert_eq!(json["created_at"], serde_json::Value::Null);
+        assert_eq!(json["record"]["link"]["/"], cid.to_string());
+    }
 }
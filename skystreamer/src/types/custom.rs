@@ -0,0 +1,211 @@
+//! Decoding of records from custom lexicons.
+//!
+//! The firehose carries records of every AT Protocol application, not just Bluesky.
+//! Register a [`RecordDecoder`] for a collection NSID in a [`DecoderRegistry`], and
+//! records of that collection are yielded as [`crate::types::commit::Record::Custom`]
+//! instead of [`crate::types::commit::Record::Other`].
+//!
+//! # Example
+//! ```no_run
+//! use serde::Deserialize;
+//! use skystreamer::{stream::EventStream, types::custom::DecoderRegistry, RepoSubscription};
+//!
+//! #[derive(Debug, Deserialize)]
+//! #[serde(rename_all = "camelCase")]
+//! struct BlogEntry {
+//!     title: Option<String>,
+//!     content: String,
+//! }
+//!
+//! let decoders = DecoderRegistry::new().register_type::<BlogEntry>("com.whtwnd.blog.entry");
+//! let subscription = RepoSubscription::new("bsky.network").await.unwrap();
+//! let mut binding = EventStream::new(subscription).with_decoders(decoders);
+//! ```
+use super::{operation::Action, AtUri};
+use crate::Result;
+use atrium_api::types::{string::Did, CidLink};
+use serde::de::DeserializeOwned;
+use std::{any::Any, collections::HashMap, fmt, marker::PhantomData, sync::Arc};
+
+/// The data of a record decoded by a [`RecordDecoder`].
+///
+/// Implemented for every `Debug + Send + Sync` type, so any type can be used.
+pub trait CustomRecordData: Any + fmt::Debug + Send + Sync {
+    /// Get the data as [`Any`], to downcast it to its concrete type
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Any + fmt::Debug + Send + Sync> CustomRecordData for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A decoder for the records of a single collection.
+///
+/// Implemented for closures taking the DAG-CBOR data of a record.
+pub trait RecordDecoder: Send + Sync {
+    /// Decode the DAG-CBOR data of a record
+    fn decode(&self, data: &[u8]) -> Result<Arc<dyn CustomRecordData>>;
+}
+
+impl<F> RecordDecoder for F
+where
+    F: Fn(&[u8]) -> Result<Arc<dyn CustomRecordData>> + Send + Sync,
+{
+    fn decode(&self, data: &[u8]) -> Result<Arc<dyn CustomRecordData>> {
+        self(data)
+    }
+}
+
+/// A [`RecordDecoder`] that deserializes records into `T` using serde
+pub struct SerdeDecoder<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for SerdeDecoder<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> RecordDecoder for SerdeDecoder<T>
+where
+    T: DeserializeOwned + CustomRecordData,
+{
+    fn decode(&self, data: &[u8]) -> Result<Arc<dyn CustomRecordData>> {
+        Ok(Arc::new(serde_ipld_dagcbor::from_slice::<T>(data)?))
+    }
+}
+
+/// A set of [`RecordDecoder`]s, keyed by collection NSID.
+///
+/// Decoders take precedence over the built-in Bluesky types, so registering
+/// a decoder for e.g. `app.bsky.feed.post` replaces [`crate::types::Post`].
+#[derive(Clone, Default)]
+pub struct DecoderRegistry {
+    decoders: HashMap<String, Arc<dyn RecordDecoder>>,
+}
+
+impl fmt::Debug for DecoderRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.decoders.keys()).finish()
+    }
+}
+
+impl DecoderRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a decoder for the given collection NSID, replacing any previous one
+    pub fn register(mut self, nsid: &str, decoder: impl RecordDecoder + 'static) -> Self {
+        self.decoders.insert(nsid.to_string(), Arc::new(decoder));
+        self
+    }
+
+    /// Register a [`SerdeDecoder`] deserializing records of the given collection NSID into `T`
+    pub fn register_type<T>(self, nsid: &str) -> Self
+    where
+        T: DeserializeOwned + CustomRecordData,
+    {
+        self.register(nsid, SerdeDecoder::<T>::default())
+    }
+
+    /// Get the decoder for the given collection NSID, if any
+    pub fn get(&self, nsid: &str) -> Option<&dyn RecordDecoder> {
+        self.decoders.get(nsid).map(|decoder| decoder.as_ref())
+    }
+
+    /// Whether no decoders are registered
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+}
+
+/// A record decoded by a user-defined [`RecordDecoder`]
+#[derive(Debug, Clone)]
+pub struct CustomRecord {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    pub cid: Option<CidLink>,
+    pub action: Action,
+    data: Arc<dyn CustomRecordData>,
+}
+
+impl CustomRecord {
+    pub fn new(
+        author: Did,
+        uri: AtUri,
+        cid: Option<CidLink>,
+        action: Action,
+        data: Arc<dyn CustomRecordData>,
+    ) -> Self {
+        Self {
+            author,
+            uri,
+            cid,
+            action,
+            data,
+        }
+    }
+
+    /// The collection NSID of the record
+    pub fn collection(&self) -> &str {
+        &self.uri.collection
+    }
+
+    /// The decoded data of the record
+    pub fn data(&self) -> &dyn CustomRecordData {
+        &*self.data
+    }
+
+    /// Get the decoded data of the record as `T`, if it is of that type
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        // Deref first, `Arc<dyn CustomRecordData>` is itself `CustomRecordData`
+        (*self.data).as_any().downcast_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Entry {
+        title: String,
+    }
+
+    #[test]
+    fn decode_registered_type() {
+        let registry = DecoderRegistry::new().register_type::<Entry>("com.whtwnd.blog.entry");
+        assert!(registry.get("app.bsky.feed.post").is_none());
+
+        let data = serde_ipld_dagcbor::to_vec(&Entry {
+            title: "Hello".to_string(),
+        })
+        .unwrap();
+        let decoded = registry
+            .get("com.whtwnd.blog.entry")
+            .unwrap()
+            .decode(&data)
+            .unwrap();
+
+        let record = CustomRecord::new(
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            "at://did:plc:z72i7hdynmk6r22z27h6tvur/com.whtwnd.blog.entry/abc"
+                .parse()
+                .unwrap(),
+            None,
+            Action::Create,
+            decoded,
+        );
+        assert_eq!(record.collection(), "com.whtwnd.blog.entry");
+        assert_eq!(
+            record.downcast_ref::<Entry>().map(|e| e.title.as_str()),
+            Some("Hello")
+        );
+        assert!(record.downcast_ref::<String>().is_none());
+    }
+}
@@ -0,0 +1,294 @@
+//! Top-level firehose events.
+//!
+//! Besides commits, the firehose also emits events about the accounts themselves,
+//! such as identity changes, account status changes and informational messages
+//! from the relay.
+
+use super::commit::Commit;
+use crate::{util::datetime_to_chrono, Result};
+use atrium_api::{
+    com::atproto::sync::subscribe_repos,
+    types::string::{Did, Handle},
+};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+/// An event emitted by the firehose.
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A commit to a repository, containing record operations
+    Commit(Box<Commit>),
+    /// An account's identity (handle or DID document) has changed
+    Identity(IdentityEvent),
+    /// An account's hosting status has changed
+    Account(AccountEvent),
+    /// An account's handle has changed (deprecated in favor of [`Event::Identity`])
+    Handle(HandleEvent),
+    /// A repository has been deleted (deprecated in favor of [`Event::Account`])
+    Tombstone(TombstoneEvent),
+    /// An informational message from the relay
+    Info(InfoEvent),
+}
+
+impl Event {
+    /// Decode an event from the type and body of a message frame.
+    ///
+    /// Returns `None` if the message type is not known to the library.
+    pub fn from_message(kind: &str, body: &[u8]) -> Result<Option<Self>> {
+        let event = match kind {
+            "#commit" => Event::Commit(Box::new(Self::commit_from_body(body)?)),
+            "#identity" => Event::Identity(
+                serde_ipld_dagcbor::from_slice::<subscribe_repos::Identity>(body)?.into(),
+            ),
+            "#account" => Event::Account(
+                serde_ipld_dagcbor::from_slice::<subscribe_repos::Account>(body)?.into(),
+            ),
+            "#handle" => Event::Handle(
+                serde_ipld_dagcbor::from_slice::<subscribe_repos::Handle>(body)?.into(),
+            ),
+            "#tombstone" => Event::Tombstone(
+                serde_ipld_dagcbor::from_slice::<subscribe_repos::Tombstone>(body)?.into(),
+            ),
+            "#info" => {
+                Event::Info(serde_ipld_dagcbor::from_slice::<subscribe_repos::Info>(body)?.into())
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+
+    /// Decode the body of a `#commit` message frame
+    pub(crate) fn commit_from_body(body: &[u8]) -> Result<Commit> {
+        let commit: subscribe_repos::Commit = serde_ipld_dagcbor::from_slice(body)?;
+        Ok(Commit::from(commit))
+    }
+
+    /// Read only the `seq` of a `#commit` message body, skipping over its blocks and ops
+    pub(crate) fn commit_seq(body: &[u8]) -> Result<i64> {
+        #[derive(Deserialize)]
+        struct CommitSeq {
+            seq: i64,
+        }
+        Ok(serde_ipld_dagcbor::from_slice::<CommitSeq>(body)?.seq)
+    }
+
+    /// Get the sequence number of the event, if it has one.
+    ///
+    /// Info messages are not part of the sequence.
+    pub fn seq(&self) -> Option<i64> {
+        match self {
+            Event::Commit(commit) => Some(commit.inner().seq),
+            Event::Identity(e) => Some(e.seq),
+            Event::Account(e) => Some(e.seq),
+            Event::Handle(e) => Some(e.seq),
+            Event::Tombstone(e) => Some(e.seq),
+            Event::Info(_) => None,
+        }
+    }
+}
+
+/// An account's identity has changed.
+///
+/// Consumers should re-resolve the DID document and handle of the account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdentityEvent {
+    pub did: Did,
+    /// The current handle of the account, if the relay included it
+    pub handle: Option<Handle>,
+    pub seq: i64,
+    pub time: DateTime<FixedOffset>,
+}
+
+impl From<subscribe_repos::Identity> for IdentityEvent {
+    fn from(value: subscribe_repos::Identity) -> Self {
+        Self {
+            did: value.data.did,
+            handle: value.data.handle,
+            seq: value.data.seq,
+            time: datetime_to_chrono(&value.data.time),
+        }
+    }
+}
+
+/// The hosting status of an inactive account
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AccountStatus {
+    Takendown,
+    Suspended,
+    Deleted,
+    Deactivated,
+    /// A status not (yet) known to the library
+    Other(String),
+}
+
+impl From<&str> for AccountStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "takendown" => AccountStatus::Takendown,
+            "suspended" => AccountStatus::Suspended,
+            "deleted" => AccountStatus::Deleted,
+            "deactivated" => AccountStatus::Deactivated,
+            other => AccountStatus::Other(other.to_string()),
+        }
+    }
+}
+
+/// An account's hosting status has changed, e.g. it was taken down or deactivated.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountEvent {
+    pub did: Did,
+    /// Whether the account's repository is currently available
+    pub active: bool,
+    /// The reason the account is inactive, if it is
+    pub status: Option<AccountStatus>,
+    pub seq: i64,
+    pub time: DateTime<FixedOffset>,
+}
+
+impl From<subscribe_repos::Account> for AccountEvent {
+    fn from(value: subscribe_repos::Account) -> Self {
+        Self {
+            did: value.data.did,
+            active: value.data.active,
+            status: value.data.status.as_deref().map(AccountStatus::from),
+            seq: value.data.seq,
+            time: datetime_to_chrono(&value.data.time),
+        }
+    }
+}
+
+/// An account's handle has changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HandleEvent {
+    pub did: Did,
+    pub handle: Handle,
+    pub seq: i64,
+    pub time: DateTime<FixedOffset>,
+}
+
+impl From<subscribe_repos::Handle> for HandleEvent {
+    fn from(value: subscribe_repos::Handle) -> Self {
+        Self {
+            did: value.data.did,
+            handle: value.data.handle,
+            seq: value.data.seq,
+            time: datetime_to_chrono(&value.data.time),
+        }
+    }
+}
+
+/// A repository has been deleted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TombstoneEvent {
+    pub did: Did,
+    pub seq: i64,
+    pub time: DateTime<FixedOffset>,
+}
+
+impl From<subscribe_repos::Tombstone> for TombstoneEvent {
+    fn from(value: subscribe_repos::Tombstone) -> Self {
+        Self {
+            did: value.data.did,
+            seq: value.data.seq,
+            time: datetime_to_chrono(&value.data.time),
+        }
+    }
+}
+
+/// The kind of an informational message from the relay
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InfoKind {
+    /// The requested cursor is older than the relay's backfill window,
+    /// so the stream started from the oldest available event instead.
+    OutdatedCursor,
+    /// A message not (yet) known to the library
+    Other(String),
+}
+
+/// An informational message from the relay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InfoEvent {
+    pub kind: InfoKind,
+    pub message: Option<String>,
+}
+
+impl From<subscribe_repos::Info> for InfoEvent {
+    fn from(value: subscribe_repos::Info) -> Self {
+        let kind = match value.data.name.as_str() {
+            "OutdatedCursor" => InfoKind::OutdatedCursor,
+            other => InfoKind::Other(other.to_string()),
+        };
+        Self {
+            kind,
+            message: value.data.message,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::types::string::Datetime;
+    use ipld_core::ipld::Ipld;
+
+    #[test]
+    fn decode_account_event() {
+        let account = subscribe_repos::Account {
+            data: subscribe_repos::AccountData {
+                active: false,
+                did: "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+                seq: 42,
+                status: Some("takendown".to_string()),
+                time: Datetime::now(),
+            },
+            extra_data: Ipld::Null,
+        };
+        let body = serde_ipld_dagcbor::to_vec(&account).unwrap();
+        let event = Event::from_message("#account", &body).unwrap().unwrap();
+
+        assert_eq!(event.seq(), Some(42));
+        let Event::Account(account) = event else {
+            panic!("expected an account event, got {event:?}");
+        };
+        assert!(!account.active);
+        assert_eq!(account.status, Some(AccountStatus::Takendown));
+    }
+
+    #[test]
+    fn commit_seq_skips_blocks() {
+        let mut commit =
+            crate::testing::CommitBuilder::new("did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap())
+                .post("3l6oveex3ii2l", "hello")
+                .build();
+        commit.seq = 42;
+        let body = serde_ipld_dagcbor::to_vec(&commit).unwrap();
+
+        assert_eq!(Event::commit_seq(&body).unwrap(), 42);
+        assert_eq!(Event::commit_from_body(&body).unwrap().inner().seq, 42);
+        assert!(Event::commit_seq(&body[..body.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn decode_info_event() {
+        let info = subscribe_repos::Info {
+            data: subscribe_repos::InfoData {
+                message: Some("cursor too old".to_string()),
+                name: "OutdatedCursor".to_string(),
+            },
+            extra_data: Ipld::Null,
+        };
+        let body = serde_ipld_dagcbor::to_vec(&info).unwrap();
+        let event = Event::from_message("#info", &body).unwrap().unwrap();
+
+        assert_eq!(event.seq(), None);
+        let Event::Info(info) = event else {
+            panic!("expected an info event, got {event:?}");
+        };
+        assert_eq!(info.kind, InfoKind::OutdatedCursor);
+    }
+
+    #[test]
+    fn unknown_message_type() {
+        assert!(Event::from_message("#unknown", &[]).unwrap().is_none());
+    }
+}
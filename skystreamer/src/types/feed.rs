@@ -2,13 +2,13 @@
 //!
 //! These events are emitted when a user interacts with a post, adding data to the feed.
 
-use crate::util::{conv_atrium_cid, datetime_to_chrono};
+use super::{operation::Action, AtUri, Blob, StrongRef};
+use crate::util::{datetime_to_chrono, self_label_values};
 use atrium_api::{
     app::bsky,
-    types::{string::Did, CidLink},
+    types::{string::Did, CidLink, Collection, Union},
 };
 use chrono::{DateTime, FixedOffset};
-use cid::Cid;
 use serde::{Deserialize, Serialize};
 
 /// An event where someone likes a post
@@ -17,20 +17,32 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LikeEvent {
     pub author: Did,
-    pub subject: Cid,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The liked post
+    pub subject: StrongRef,
     pub created_at: DateTime<FixedOffset>,
     pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
 }
 
 impl LikeEvent {
-    pub fn new(author: Did, record: bsky::feed::like::Record, cid: Option<CidLink>) -> Self {
+    pub fn new(
+        author: Did,
+        record: bsky::feed::like::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         // let subject = record.subject.cid.as_ref();
 
         Self {
+            uri: AtUri::new(author.clone(), bsky::feed::Like::NSID, rkey),
             author,
             created_at: datetime_to_chrono(&record.created_at),
-            subject: conv_atrium_cid(&record.subject.cid),
+            subject: record.data.subject.into(),
             cid,
+            action: Action::Create,
         }
     }
 }
@@ -41,18 +53,330 @@ impl LikeEvent {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RepostEvent {
     pub author: Did,
-    pub subject: Cid,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The reposted post
+    pub subject: StrongRef,
     pub created_at: DateTime<FixedOffset>,
     pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
 }
 
 impl RepostEvent {
-    pub fn new(author: Did, record: bsky::feed::repost::Record, cid: Option<CidLink>) -> Self {
+    pub fn new(
+        author: Did,
+        record: bsky::feed::repost::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         Self {
+            uri: AtUri::new(author.clone(), bsky::feed::Repost::NSID, rkey),
             author,
             created_at: datetime_to_chrono(&record.created_at),
-            subject: conv_atrium_cid(&record.subject.cid),
+            subject: record.data.subject.into(),
             cid,
+            action: Action::Create,
         }
     }
 }
+
+/// An event where someone publishes a custom feed generator
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedGeneratorEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The DID of the service that serves the feed
+    pub did: Did,
+    pub display_name: String,
+    pub description: Option<String>,
+    pub avatar: Option<Blob>,
+    /// Whether the feed generator accepts interactions (e.g. "show more/less like this")
+    pub accepts_interactions: bool,
+    /// Self-imposed labels on the feed generator
+    pub labels: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl FeedGeneratorEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::feed::generator::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::feed::Generator::NSID, rkey),
+            author,
+            did: record.did,
+            display_name: record.display_name,
+            description: record.description,
+            avatar: record.avatar.map(Blob::from),
+            accepts_interactions: record.accepts_interactions.unwrap_or_default(),
+            labels: match record.labels {
+                Some(Union::Refs(
+                    bsky::feed::generator::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels),
+                )) => self_label_values(&labels),
+                _ => vec![],
+            },
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
+
+/// A rule allowing a group of users to reply to a thread
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThreadgateRule {
+    /// Users mentioned in the post can reply
+    Mention,
+    /// Users followed by the author can reply
+    Following,
+    /// Members of the list with the given AT-URI can reply
+    List(String),
+    /// A rule from a lexicon this library does not know yet, with its `$type`
+    Other(String),
+}
+
+/// An event where someone restricts who can reply to their post
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThreadgateEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The AT-URI of the gated post
+    pub post: String,
+    /// Who can reply to the thread.
+    ///
+    /// `None` means anyone can reply, while an empty list means nobody can.
+    pub allow: Option<Vec<ThreadgateRule>>,
+    /// AT-URIs of replies the author has hidden from the thread
+    pub hidden_replies: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl ThreadgateEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::feed::threadgate::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        use bsky::feed::threadgate::RecordAllowItem;
+
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::feed::Threadgate::NSID, rkey),
+            author,
+            post: record.post,
+            allow: record.allow.map(|rules| {
+                rules
+                    .into_iter()
+                    .map(|rule| match rule {
+                        Union::Refs(RecordAllowItem::MentionRule(_)) => ThreadgateRule::Mention,
+                        Union::Refs(RecordAllowItem::FollowingRule(_)) => ThreadgateRule::Following,
+                        Union::Refs(RecordAllowItem::ListRule(rule)) => {
+                            ThreadgateRule::List(rule.data.list)
+                        }
+                        // Keep unknown rules, dropping them could leave a gate that allows nobody
+                        Union::Unknown(rule) => ThreadgateRule::Other(rule.r#type),
+                    })
+                    .collect()
+            }),
+            hidden_replies: record.hidden_replies.unwrap_or_default(),
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
+
+/// A rule restricting how a post can be embedded
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostgateRule {
+    /// Nobody can embed the post
+    Disable,
+    /// A rule from a lexicon this library does not know yet, with its `$type`
+    Other(String),
+}
+
+/// An event where someone restricts how their post can be embedded (quoted)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PostgateEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The AT-URI of the gated post
+    pub post: String,
+    /// AT-URIs of posts that have been detached from quoting the gated post
+    pub detached_embedding_uris: Vec<String>,
+    /// The rules restricting how the post can be embedded, empty if there are none
+    pub embedding_rules: Vec<PostgateRule>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl PostgateEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::feed::postgate::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        use bsky::feed::postgate::RecordEmbeddingRulesItem;
+
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::feed::Postgate::NSID, rkey),
+            author,
+            post: record.post,
+            detached_embedding_uris: record.detached_embedding_uris.unwrap_or_default(),
+            embedding_rules: record
+                .embedding_rules
+                .unwrap_or_default()
+                .into_iter()
+                .map(|rule| match rule {
+                    Union::Refs(RecordEmbeddingRulesItem::DisableRule(_)) => PostgateRule::Disable,
+                    // Keep unknown rules, so consumers can tell the gate is not fully understood
+                    Union::Unknown(rule) => PostgateRule::Other(rule.r#type),
+                })
+                .collect(),
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+
+    /// Whether quoting the post is disabled altogether.
+    ///
+    /// Rules in [`PostgateRule::Other`] are not taken into account.
+    pub fn embedding_disabled(&self) -> bool {
+        self.embedding_rules.contains(&PostgateRule::Disable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::{
+        app::bsky::feed::{postgate, threadgate},
+        types::{string::Datetime, UnknownData},
+    };
+    use ipld_core::ipld::Ipld;
+
+    #[test]
+    fn threadgate_rules() {
+        let rule = |item| Union::Refs(item);
+        let record: threadgate::Record = threadgate::RecordData {
+            allow: Some(vec![
+                rule(threadgate::RecordAllowItem::MentionRule(Box::new(
+                    threadgate::MentionRuleData {}.into(),
+                ))),
+                rule(threadgate::RecordAllowItem::ListRule(Box::new(
+                    threadgate::ListRuleData {
+                        list: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.graph.list/abc"
+                            .to_string(),
+                    }
+                    .into(),
+                ))),
+            ]),
+            created_at: Datetime::now(),
+            hidden_replies: None,
+            post: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l"
+                .to_string(),
+        }
+        .into();
+
+        let event = ThreadgateEvent::new(
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            record,
+            None,
+            "3l6oveex3ii2l",
+        );
+        assert_eq!(
+            event.allow,
+            Some(vec![
+                ThreadgateRule::Mention,
+                ThreadgateRule::List(
+                    "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.graph.list/abc".to_string()
+                ),
+            ])
+        );
+        assert_eq!(event.uri.collection, "app.bsky.feed.threadgate");
+        assert!(event.hidden_replies.is_empty());
+    }
+
+    #[test]
+    fn threadgate_unknown_rules() {
+        let record: threadgate::Record = threadgate::RecordData {
+            allow: Some(vec![Union::Unknown(UnknownData {
+                r#type: "app.bsky.feed.threadgate#followerRule".to_string(),
+                data: Ipld::Map(Default::default()),
+            })]),
+            created_at: Datetime::now(),
+            hidden_replies: None,
+            post: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l"
+                .to_string(),
+        }
+        .into();
+
+        let event = ThreadgateEvent::new(
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            record,
+            None,
+            "3l6oveex3ii2l",
+        );
+        assert_eq!(
+            event.allow,
+            Some(vec![ThreadgateRule::Other(
+                "app.bsky.feed.threadgate#followerRule".to_string()
+            )])
+        );
+    }
+
+    #[test]
+    fn postgate_unknown_rules() {
+        let record: postgate::Record = postgate::RecordData {
+            created_at: Datetime::now(),
+            detached_embedding_uris: None,
+            embedding_rules: Some(vec![
+                Union::Refs(postgate::RecordEmbeddingRulesItem::DisableRule(Box::new(
+                    postgate::DisableRuleData {}.into(),
+                ))),
+                Union::Unknown(UnknownData {
+                    r#type: "app.bsky.feed.postgate#followerRule".to_string(),
+                    data: Ipld::Map(Default::default()),
+                }),
+            ]),
+            post: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l"
+                .to_string(),
+        }
+        .into();
+
+        let event = PostgateEvent::new(
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            record,
+            None,
+            "3l6oveex3ii2l",
+        );
+        assert_eq!(
+            event.embedding_rules,
+            vec![
+                PostgateRule::Disable,
+                PostgateRule::Other("app.bsky.feed.postgate#followerRule".to_string()),
+            ]
+        );
+        assert!(event.embedding_disabled());
+    }
+}
@@ -2,10 +2,11 @@
 //! connections. These events are emitted when a user does something related
 //! to another user.
 
-use crate::util::datetime_to_chrono;
+use super::{operation::Action, AtUri, Blob};
+use crate::util::{datetime_to_chrono, self_label_values};
 use atrium_api::{
     app::bsky::{self},
-    types::{string::Did, CidLink},
+    types::{string::Did, CidLink, Collection, Union},
 };
 use chrono::{DateTime, FixedOffset};
 use serde::{Deserialize, Serialize};
@@ -16,18 +17,29 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockEvent {
     pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
     pub subject: Did,
     pub created_at: DateTime<FixedOffset>,
     pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
 }
 
 impl BlockEvent {
-    pub fn new(author: Did, record: bsky::graph::block::Record, cid: Option<CidLink>) -> Self {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::block::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         Self {
+            uri: AtUri::new(author.clone(), bsky::graph::Block::NSID, rkey),
             author,
             created_at: datetime_to_chrono(&record.created_at),
             subject: record.subject.clone(),
             cid,
+            action: Action::Create,
         }
     }
 }
@@ -36,18 +48,29 @@ impl BlockEvent {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FollowEvent {
     pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
     pub subject: Did,
     pub created_at: DateTime<FixedOffset>,
     pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
 }
 
 impl FollowEvent {
-    pub fn new(author: Did, record: bsky::graph::follow::Record, cid: Option<CidLink>) -> Self {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::follow::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         Self {
+            uri: AtUri::new(author.clone(), bsky::graph::Follow::NSID, rkey),
             author,
             created_at: datetime_to_chrono(&record.created_at),
             subject: record.subject.clone(),
             cid,
+            action: Action::Create,
         }
     }
 }
@@ -56,20 +79,155 @@ impl FollowEvent {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ListItemEvent {
     pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
     pub subject: Did,
     pub created_at: DateTime<FixedOffset>,
     pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
     pub list: String,
 }
 
 impl ListItemEvent {
-    pub fn new(author: Did, record: bsky::graph::listitem::Record, cid: Option<CidLink>) -> Self {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::listitem::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
         Self {
+            uri: AtUri::new(author.clone(), bsky::graph::Listitem::NSID, rkey),
             author,
             created_at: datetime_to_chrono(&record.created_at),
             subject: record.subject.clone(),
             cid,
+            action: Action::Create,
             list: record.list.clone(),
         }
     }
 }
+
+/// An event where someone creates a list of users, e.g. a moderation or curation list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    pub name: String,
+    /// What the list is for, e.g. `app.bsky.graph.defs#modlist` or `app.bsky.graph.defs#curatelist`
+    pub purpose: String,
+    pub description: Option<String>,
+    pub avatar: Option<Blob>,
+    /// Self-imposed labels on the list
+    pub labels: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl ListEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::list::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::graph::List::NSID, rkey),
+            author,
+            name: record.name,
+            purpose: record.purpose,
+            description: record.description,
+            avatar: record.avatar.map(Blob::from),
+            labels: match record.labels {
+                Some(Union::Refs(
+                    bsky::graph::list::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels),
+                )) => self_label_values(&labels),
+                _ => vec![],
+            },
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
+
+/// An event where someone blocks every user on a list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListBlockEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The AT-URI of the blocked list
+    pub subject: String,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl ListBlockEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::listblock::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        Self {
+            uri: AtUri::new(author.clone(), bsky::graph::Listblock::NSID, rkey),
+            author,
+            created_at: datetime_to_chrono(&record.created_at),
+            subject: record.subject.clone(),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
+
+/// An event where someone creates a starter pack of users and feeds to follow
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StarterPackEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    pub name: String,
+    pub description: Option<String>,
+    /// The AT-URI of the list of users in the starter pack
+    pub list: String,
+    /// AT-URIs of the feed generators in the starter pack
+    pub feeds: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl StarterPackEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::graph::starterpack::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::graph::Starterpack::NSID, rkey),
+            author,
+            name: record.name,
+            description: record.description,
+            list: record.list,
+            feeds: record
+                .feeds
+                .unwrap_or_default()
+                .into_iter()
+                .map(|feed| feed.data.uri)
+                .collect(),
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Helpers for raw IPLD data, such as records of unknown collections.
+//!
+//! Unknown records are kept as an [`Ipld`] tree, which preserves byte strings and
+//! CID links exactly. These helpers convert that tree to and from DAG-JSON, where
+//! links are encoded as `{"/": "<cid>"}` and bytes as `{"/": {"bytes": "<base64>"}}`,
+//! and back to DAG-CBOR, the encoding records are stored in.
+use crate::Result;
+use ipld_core::ipld::Ipld;
+use serde::{de::DeserializeOwned, ser::SerializeMap, Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Encode an IPLD tree as DAG-JSON
+pub fn to_dag_json(ipld: &Ipld) -> Result<String> {
+    let json = serde_ipld_dagjson::to_vec(ipld)?;
+    // DAG-JSON is always valid UTF-8
+    Ok(String::from_utf8(json).expect("DAG-JSON encoder produced invalid UTF-8"))
+}
+
+/// Decode an IPLD tree from DAG-JSON
+pub fn from_dag_json(json: &str) -> Result<Ipld> {
+    Ok(serde_ipld_dagjson::from_slice(json.as_bytes())?)
+}
+
+/// Encode an IPLD tree as DAG-CBOR.
+///
+/// Decoding a record into [`Ipld`] and encoding it again yields the original bytes,
+/// as long as the record was encoded canonically in the first place.
+pub fn to_dag_cbor(ipld: &Ipld) -> Result<Vec<u8>> {
+    Ok(serde_ipld_dagcbor::to_vec(ipld)?)
+}
+
+/// Deserialize an IPLD tree into a typed value, e.g. a record type of a lexicon
+/// not known to this library
+pub fn from_ipld<T: DeserializeOwned>(ipld: Ipld) -> Result<T> {
+    Ok(ipld_core::serde::from_ipld(ipld)?)
+}
+
+/// Serializes an IPLD tree in its DAG-JSON form to human-readable formats, and as
+/// is to binary formats, which can encode links and bytes natively.
+pub(crate) struct DagJson<'a>(pub &'a Ipld);
+
+impl Serialize for DagJson<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        if !serializer.is_human_readable() {
+            return self.0.serialize(serializer);
+        }
+        match self.0 {
+            Ipld::Null => serializer.serialize_unit(),
+            Ipld::Bool(b) => serializer.serialize_bool(*b),
+            Ipld::Integer(i) => serializer.serialize_i128(*i),
+            Ipld::Float(f) => serializer.serialize_f64(*f),
+            Ipld::String(s) => serializer.serialize_str(s),
+            Ipld::Bytes(bytes) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(
+                    "/",
+                    &BTreeMap::from([("bytes", multibase::Base::Base64.encode(bytes))]),
+                )?;
+                map.end()
+            }
+            Ipld::List(list) => serializer.collect_seq(list.iter().map(DagJson)),
+            Ipld::Map(map) => {
+                serializer.collect_map(map.iter().map(|(key, value)| (key, DagJson(value))))
+            }
+            Ipld::Link(cid) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry("/", &cid.to_string())?;
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cid::Cid;
+    use std::collections::BTreeMap;
+
+    fn record() -> Ipld {
+        let cid: Cid = "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+            .parse()
+            .unwrap();
+        Ipld::Map(BTreeMap::from([
+            (
+                "$type".to_string(),
+                Ipld::String("com.example.record".into()),
+            ),
+            ("data".to_string(), Ipld::Bytes(vec![0, 1, 2, 255])),
+            ("link".to_string(), Ipld::Link(cid)),
+            ("count".to_string(), Ipld::Integer(42)),
+        ]))
+    }
+
+    #[test]
+    fn dag_json_round_trip() {
+        let json = to_dag_json(&record()).unwrap();
+        assert_eq!(
+            json,
+            r#"{"$type":"com.example.record","count":42,"data":{"/":{"bytes":"AAEC/w"}},"link":{"/":"bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"}}"#
+        );
+        assert_eq!(from_dag_json(&json).unwrap(), record());
+    }
+
+    #[test]
+    fn dag_json_serialize() {
+        assert_eq!(
+            serde_json::to_string(&DagJson(&record())).unwrap(),
+            to_dag_json(&record()).unwrap()
+        );
+    }
+
+    #[test]
+    fn dag_cbor_round_trip() {
+        let cbor = to_dag_cbor(&record()).unwrap();
+        let decoded: Ipld = serde_ipld_dagcbor::from_slice(&cbor).unwrap();
+        assert_eq!(decoded, record());
+        assert_eq!(to_dag_cbor(&decoded).unwrap(), cbor);
+    }
+}
@@ -0,0 +1,54 @@
+//! Helper types for bsky labeler events.
+//!
+//! Labelers are moderation services that publish labels on accounts and content.
+//! These events are emitted when an account declares itself as a labeler.
+
+use super::{operation::Action, AtUri};
+use crate::util::{datetime_to_chrono, self_label_values};
+use atrium_api::{
+    app::bsky,
+    types::{string::Did, CidLink, Collection, Union},
+};
+use chrono::{DateTime, FixedOffset};
+use serde::{Deserialize, Serialize};
+
+/// An event where someone declares their account as a labeler service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LabelerServiceEvent {
+    pub author: Did,
+    /// The AT-URI of this record
+    pub uri: AtUri,
+    /// The label values the labeler publishes
+    pub label_values: Vec<String>,
+    /// Self-imposed labels on the labeler
+    pub labels: Vec<String>,
+    pub created_at: DateTime<FixedOffset>,
+    pub cid: Option<CidLink>,
+    #[serde(default)]
+    pub action: Action,
+}
+
+impl LabelerServiceEvent {
+    pub fn new(
+        author: Did,
+        record: bsky::labeler::service::Record,
+        cid: Option<CidLink>,
+        rkey: &str,
+    ) -> Self {
+        let record = record.data;
+        Self {
+            uri: AtUri::new(author.clone(), bsky::labeler::Service::NSID, rkey),
+            author,
+            label_values: record.policies.data.label_values,
+            labels: match record.labels {
+                Some(Union::Refs(
+                    bsky::labeler::service::RecordLabelsRefs::ComAtprotoLabelDefsSelfLabels(labels),
+                )) => self_label_values(&labels),
+                _ => vec![],
+            },
+            created_at: datetime_to_chrono(&record.created_at),
+            cid,
+            action: Action::Create,
+        }
+    }
+}
@@ -2,11 +2,13 @@ use crate::{util::datetime_to_chrono, Result};
 use atrium_api::{
     app::bsky::{
         embed::{
-            images::ImageData, record_with_media::MainMediaRefs, video::MainData as VideoData,
+            images::{ImageData, Main as ImagesMain},
+            record_with_media::MainMediaRefs,
+            video::{CaptionData, MainData as VideoData},
         },
         feed::post::{Record as PostRecord, RecordEmbedRefs},
     },
-    types::{string::Did, BlobRef, CidLink, TypedBlobRef, UnTypedBlobRef},
+    types::{string::Did, BlobRef, CidLink, Collection, TypedBlobRef, UnTypedBlobRef, Union},
 };
 use cid::multihash::Multihash;
 use cid::Cid;
@@ -14,10 +16,18 @@ use ipld_core::ipld::Ipld;
 use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 pub mod actor;
+pub mod at_uri;
+pub mod chat;
 pub mod commit;
+pub mod custom;
+pub mod event;
 pub mod feed;
 pub mod graph;
+pub mod ipld;
+pub mod labeler;
 pub mod operation;
+pub mod richtext;
+pub use at_uri::AtUri;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Media {
@@ -26,8 +36,28 @@ pub enum Media {
     // note: weird naming
     // Video(VideoData),
     Video(Video),
+    /// A link card, attached next to a quoted record.
+    ///
+    /// Link cards on their own are [`Embed::External`].
+    External(ExternalLink),
 }
 
+impl Media {
+    /// Get the blob of the media, i.e. the image, the video or the link card's thumbnail
+    pub fn blob(&self) -> Option<&Blob> {
+        match self {
+            Media::Image(image) => Some(&image.blob),
+            Media::Video(video) => Some(&video.blob),
+            Media::External(link) => link.thumb.as_ref(),
+        }
+    }
+}
+
+/// The Bluesky CDN serving images and video thumbnails
+const IMAGE_CDN: &str = "https://cdn.bsky.app/img";
+/// The Bluesky CDN serving videos
+const VIDEO_CDN: &str = "https://video.bsky.app/watch";
+
 // We have to define our own wrapper types because
 // BlobRef union type are kinda weird
 // todo: Figure out how to fix this
@@ -38,6 +68,34 @@ pub struct Video {
     pub alt: Option<String>,
     pub blob: Blob,
     pub aspect_ratio: Option<(u32, u32)>,
+    /// Subtitle files, one per language
+    #[serde(default)]
+    pub captions: Vec<Caption>,
+}
+
+impl Video {
+    /// Get the languages the video has captions in
+    pub fn langs(&self) -> impl Iterator<Item = &str> {
+        self.captions.iter().map(|caption| caption.lang.as_str())
+    }
+
+    /// Get the URL of the video's thumbnail on the Bluesky CDN, given the DID of the poster
+    pub fn thumbnail_url(&self, did: &Did) -> String {
+        format!(
+            "{VIDEO_CDN}/{}/{}/thumbnail.jpg",
+            did.as_str(),
+            self.blob.cid
+        )
+    }
+
+    /// Get the URL of the video's HLS playlist on the Bluesky CDN, given the DID of the poster
+    pub fn playlist_url(&self, did: &Did) -> String {
+        format!(
+            "{VIDEO_CDN}/{}/{}/playlist.m3u8",
+            did.as_str(),
+            self.blob.cid
+        )
+    }
 }
 
 impl From<VideoData> for Video {
@@ -48,6 +106,30 @@ impl From<VideoData> for Video {
             aspect_ratio: value
                 .aspect_ratio
                 .map(|ar| (ar.width.get() as u32, ar.height.get() as u32)),
+            captions: value
+                .captions
+                .unwrap_or_default()
+                .into_iter()
+                .map(|caption| caption.data.into())
+                .collect(),
+        }
+    }
+}
+
+/// A subtitle file of a video
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Caption {
+    /// The language of the captions
+    pub lang: String,
+    /// The caption file, in WebVTT format
+    pub file: Blob,
+}
+
+impl From<CaptionData> for Caption {
+    fn from(value: CaptionData) -> Self {
+        Self {
+            lang: value.lang.as_ref().to_string(),
+            file: value.file.into(),
         }
     }
 }
@@ -59,6 +141,26 @@ pub struct Image {
     pub aspect_ratio: Option<(u32, u32)>,
 }
 
+impl Image {
+    /// Get the URL of the image's thumbnail on the Bluesky CDN, given the DID of the poster
+    pub fn thumbnail_url(&self, did: &Did) -> String {
+        format!(
+            "{IMAGE_CDN}/feed_thumbnail/plain/{}/{}@jpeg",
+            did.as_str(),
+            self.blob.cid
+        )
+    }
+
+    /// Get the URL of the full size image on the Bluesky CDN, given the DID of the poster
+    pub fn fullsize_url(&self, did: &Did) -> String {
+        format!(
+            "{IMAGE_CDN}/feed_fullsize/plain/{}/{}@jpeg",
+            did.as_str(),
+            self.blob.cid
+        )
+    }
+}
+
 impl From<ImageData> for Image {
     fn from(value: ImageData) -> Self {
         Self {
@@ -119,17 +221,62 @@ impl From<BlobRef> for Blob {
         }
     }
 }
+/// A strong reference to a record: its AT-URI, and the CID of the exact
+/// version of the record being referenced.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct StrongRef {
+    pub uri: String,
+    #[serde(with = "crate::util::cid_string")]
+    pub cid: Cid,
+}
+
+impl StrongRef {
+    /// Parse the URI of the referenced record, if it is a valid record AT-URI
+    pub fn at_uri(&self) -> Option<AtUri> {
+        self.uri.parse().ok()
+    }
+
+    /// Get the DID of the repository the referenced record lives in,
+    /// i.e. the author of the liked, reposted or quoted post
+    pub fn did(&self) -> Option<Did> {
+        self.uri
+            .strip_prefix("at://")?
+            .split('/')
+            .next()?
+            .parse()
+            .ok()
+    }
+
+    /// Get the collection NSID of the referenced record, e.g. `app.bsky.feed.post`
+    pub fn collection(&self) -> Option<&str> {
+        self.uri
+            .strip_prefix("at://")?
+            .split('/')
+            .nth(1)
+            .filter(|collection| !collection.is_empty())
+    }
+}
+
+impl From<atrium_api::com::atproto::repo::strong_ref::Main> for StrongRef {
+    fn from(value: atrium_api::com::atproto::repo::strong_ref::Main) -> Self {
+        Self {
+            uri: value.data.uri,
+            cid: value.data.cid.as_ref().to_owned(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ReplyRef {
-    pub parent: Cid,
-    pub root: Cid,
+    pub parent: StrongRef,
+    pub root: StrongRef,
 }
 
 impl From<atrium_api::app::bsky::feed::post::ReplyRef> for ReplyRef {
     fn from(value: atrium_api::app::bsky::feed::post::ReplyRef) -> Self {
         Self {
-            parent: value.parent.data.cid.as_ref().to_owned(),
-            root: value.root.data.cid.as_ref().to_owned(),
+            parent: value.data.parent.into(),
+            root: value.data.root.into(),
         }
     }
 }
@@ -138,60 +285,103 @@ impl From<atrium_api::app::bsky::feed::post::ReplyRef> for ReplyRef {
 pub enum Embed {
     Media(Vec<Media>),
     External(ExternalLink),
-    Record(Cid),
-    RecordWithMedia(Cid, Box<Vec<Media>>),
+    Record(StrongRef),
+    RecordWithMedia(StrongRef, Box<Vec<Media>>),
     Unknown,
 }
 
-impl From<RecordEmbedRefs> for Embed {
-    fn from(value: RecordEmbedRefs) -> Self {
+impl Embed {
+    /// Get the images or video attached to the post, if any.
+    ///
+    /// Link cards are never included, even next to a quoted record,
+    /// see [`Embed::external`] to get those.
+    pub fn media(&self) -> Vec<Media> {
+        let media = match self {
+            Embed::Media(media) => media.as_slice(),
+            Embed::RecordWithMedia(_, media) => media.as_slice(),
+            _ => &[],
+        };
+        media
+            .iter()
+            .filter(|media| !matches!(media, Media::External(_)))
+            .cloned()
+            .collect()
+    }
+
+    /// Get the link card of the post, if any, whether or not it is next to a quoted record
+    pub fn external(&self) -> Option<&ExternalLink> {
+        match self {
+            Embed::External(link) => Some(link),
+            Embed::RecordWithMedia(_, media) => media.iter().find_map(|media| match media {
+                Media::External(link) => Some(link),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Get the record quoted by the post, if any
+    pub fn record(&self) -> Option<&StrongRef> {
+        match self {
+            Embed::Record(record) | Embed::RecordWithMedia(record, _) => Some(record),
+            _ => None,
+        }
+    }
+}
+
+fn images_to_media(images: &ImagesMain) -> Vec<Media> {
+    images
+        .images
+        .iter()
+        .map(|image| Media::Image(image.data.clone().into()))
+        .collect()
+}
+
+fn media_refs_to_media(refs: &MainMediaRefs) -> Vec<Media> {
+    match refs {
+        MainMediaRefs::AppBskyEmbedImagesMain(m) => images_to_media(m),
+        MainMediaRefs::AppBskyEmbedVideoMain(m) => vec![Media::Video(m.data.clone().into())],
+        MainMediaRefs::AppBskyEmbedExternalMain(m) => {
+            vec![Media::External(m.data.external.data.clone().into())]
+        }
+    }
+}
+
+impl From<&RecordEmbedRefs> for Embed {
+    fn from(value: &RecordEmbedRefs) -> Self {
         match value {
-            RecordEmbedRefs::AppBskyEmbedImagesMain(m) => Embed::Media(
-                m.images
-                    .clone()
-                    .into_iter()
-                    .map(|i| Media::Image(i.data.into()))
-                    .collect(),
-            ),
+            RecordEmbedRefs::AppBskyEmbedImagesMain(m) => Embed::Media(images_to_media(m)),
             RecordEmbedRefs::AppBskyEmbedExternalMain(m) => {
-                Embed::External(m.data.external.data.into())
+                Embed::External(m.data.external.data.clone().into())
             }
             RecordEmbedRefs::AppBskyEmbedVideoMain(m) => {
                 Embed::Media(vec![Media::Video(m.data.clone().into())])
             }
             RecordEmbedRefs::AppBskyEmbedRecordMain(m) => {
-                Embed::Record(m.record.data.cid.as_ref().to_owned())
+                Embed::Record(m.data.record.clone().into())
             }
             RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(m) => {
                 let media = match &m.media {
-                    atrium_api::types::Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(m)) => m
-                        .images
-                        .clone()
-                        .into_iter()
-                        .map(|i| Media::Image(i.data.into()))
-                        .collect::<Vec<Media>>(),
-                    atrium_api::types::Union::Refs(MainMediaRefs::AppBskyEmbedVideoMain(m)) => {
-                        vec![Media::Video(m.data.clone().into())]
-                    }
-                    _ => return Embed::Unknown,
+                    Union::Refs(refs) => media_refs_to_media(refs),
+                    Union::Unknown(_) => return Embed::Unknown,
                 };
-                // let media = match m.media {
-                //     atrium_api::types::Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(m)) => {
-                //         // Media::Image(
-                //             m.images
-                //                 .into_iter()
-                //                 .map(|i| i.data.into())
-                //                 .collect::<Vec<Image>>(),
-                //         // )
-                //     }
-                //     MainMediaRefs::AppBskyEmbedVideoMain(m) => Media::Video(m.data.into()),
-                //     _ => return Embed::Unknown,
-                // };
-                Embed::RecordWithMedia(
-                    m.record.data.record.cid.as_ref().to_owned(),
-                    Box::new(media),
-                )
-            } // _ => Embed::Unknown,
+                Embed::RecordWithMedia(m.data.record.data.record.clone().into(), Box::new(media))
+            }
+        }
+    }
+}
+
+impl From<RecordEmbedRefs> for Embed {
+    fn from(value: RecordEmbedRefs) -> Self {
+        Self::from(&value)
+    }
+}
+
+impl From<&Union<RecordEmbedRefs>> for Embed {
+    fn from(value: &Union<RecordEmbedRefs>) -> Self {
+        match value {
+            Union::Refs(refs) => refs.into(),
+            Union::Unknown(_) => Embed::Unknown,
         }
     }
 }
@@ -202,8 +392,11 @@ impl From<RecordEmbedRefs> for Embed {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Post {
     pub author: Did,
+    /// The AT-URI of the post
+    pub uri: AtUri,
     pub created_at: chrono::DateTime<chrono::FixedOffset>,
     pub text: String,
+    #[serde(with = "crate::util::cid_string")]
     pub id: Cid,
     // pub cid: String,
     pub language: Vec<String>,
@@ -211,12 +404,18 @@ pub struct Post {
     pub tags: Vec<String>,
     pub labels: Vec<String>,
     pub embed: Option<Embed>,
+    /// Mentions, links and hashtags in the text
+    #[serde(default)]
+    pub facets: Vec<richtext::Facet>,
+    /// Whether the post was created or edited
+    #[serde(default)]
+    pub action: operation::Action,
 }
 
 impl Post {
     /// Directly create a Post from a Record type by automatically creating a new PostData
-    pub fn from_record(author: Did, cid: CidLink, record: PostRecord) -> Self {
-        Self::from(PostData::new(author, cid, record))
+    pub fn from_record(author: Did, cid: CidLink, record: PostRecord, rkey: &str) -> Self {
+        Self::from(PostData::new(author, cid, record, rkey))
     }
 
     /// Get media associated with the post, if any
@@ -227,19 +426,42 @@ impl Post {
     ///
     /// This function abstracts that away and returns a vector of Media types
     pub fn get_post_media(&self) -> Vec<Media> {
-        let mut media = vec![];
-        if let Some(embed) = self.embed.as_ref() {
-            match embed {
-                crate::types::Embed::RecordWithMedia(_, m) => {
-                    media.extend(m.iter().cloned());
-                }
-                crate::types::Embed::Media(m) => {
-                    media.extend(m.iter().cloned());
-                }
-                _ => {}
-            }
-        }
-        media
+        self.embed.as_ref().map(Embed::media).unwrap_or_default()
+    }
+
+    /// Get the DIDs of all accounts mentioned in the text
+    pub fn mentions(&self) -> impl Iterator<Item = &Did> {
+        self.facet_features().filter_map(|feature| match feature {
+            richtext::FacetFeature::Mention(did) => Some(did),
+            _ => None,
+        })
+    }
+
+    /// Get the URLs of all links in the text
+    pub fn links(&self) -> impl Iterator<Item = &str> {
+        self.facet_features().filter_map(|feature| match feature {
+            richtext::FacetFeature::Link(uri) => Some(uri.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Get all hashtags in the text, without the leading `#`.
+    ///
+    /// Unlike [`Post::tags`], these are written inline in the text.
+    pub fn hashtags(&self) -> impl Iterator<Item = &str> {
+        self.facet_features().filter_map(|feature| match feature {
+            richtext::FacetFeature::Tag(tag) => Some(tag.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Get the part of the text a facet annotates, if its byte range is valid for the text
+    pub fn facet_text(&self, facet: &richtext::Facet) -> Option<&str> {
+        facet.slice(&self.text)
+    }
+
+    fn facet_features(&self) -> impl Iterator<Item = &richtext::FacetFeature> {
+        self.facets.iter().flat_map(|facet| facet.features.iter())
     }
 }
 
@@ -248,6 +470,7 @@ impl From<PostData> for Post {
         let record = value.record.data;
         Self {
             author: value.author,
+            uri: value.uri,
             // because for some reason we can't access the inner chrono::DateTime
             // we will have to reparse it from string
             created_at: datetime_to_chrono(&record.created_at),
@@ -270,10 +493,14 @@ impl From<PostData> for Post {
                     _ => Vec::new(),
                 }
             }),
-            embed: record.embed.as_ref().map(|embed| match embed {
-                atrium_api::types::Union::Refs(refs) => refs.clone().into(),
-                _ => Embed::Unknown,
-            }),
+            facets: record
+                .facets
+                .unwrap_or_default()
+                .into_iter()
+                .map(richtext::Facet::from)
+                .collect(),
+            embed: record.embed.as_ref().map(Embed::from),
+            action: operation::Action::Create,
         }
     }
 }
@@ -301,13 +528,20 @@ impl From<PostData> for Post {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PostData {
     pub author: Did,
+    pub uri: AtUri,
+    #[serde(with = "crate::util::cid_string")]
     pub cid: Cid,
     pub record: PostRecord,
 }
 
 impl PostData {
-    pub fn new(author: Did, cid: CidLink, record: PostRecord) -> Self {
+    pub fn new(author: Did, cid: CidLink, record: PostRecord, rkey: &str) -> Self {
         Self {
+            uri: AtUri::new(
+                author.clone(),
+                atrium_api::app::bsky::feed::Post::NSID,
+                rkey,
+            ),
             author,
             cid: cid.0,
             record,
@@ -315,37 +549,11 @@ impl PostData {
     }
 
     /// Get media associated with the post
+    ///
+    /// See [`Embed::media`] for which media is included.
     pub fn get_media(&self) -> Option<Vec<Media>> {
-        let embed = self.record.embed.as_ref()?;
-
-        match embed {
-            atrium_api::types::Union::Refs(RecordEmbedRefs::AppBskyEmbedImagesMain(m)) => Some(
-                m.images
-                    .iter()
-                    .map(|i| Media::Image(i.data.clone().into()))
-                    .collect(),
-            ),
-            atrium_api::types::Union::Refs(RecordEmbedRefs::AppBskyEmbedVideoMain(m)) => {
-                Some(vec![Media::Video(m.data.clone().into())])
-            }
-            atrium_api::types::Union::Refs(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(m)) => {
-                match &m.media {
-                    atrium_api::types::Union::Refs(MainMediaRefs::AppBskyEmbedImagesMain(m)) => {
-                        Some(
-                            m.images
-                                .iter()
-                                .map(|i| Media::Image(i.data.clone().into()))
-                                .collect(),
-                        )
-                    }
-                    atrium_api::types::Union::Refs(MainMediaRefs::AppBskyEmbedVideoMain(m)) => {
-                        Some(vec![Media::Video(m.data.clone().into())])
-                    }
-                    _ => None,
-                }
-            }
-            _ => None,
-        }
+        let media = Embed::from(self.record.embed.as_ref()?).media();
+        (!media.is_empty()).then_some(media)
     }
 
     // pub fn
@@ -360,10 +568,7 @@ pub async fn download_media<C>(
 where
     C: atrium_api::xrpc::XrpcClient + Send + Sync,
 {
-    let blob_ref = match media {
-        Media::Image(data) => &data.blob,
-        Media::Video(data) => &data.blob,
-    };
+    let blob_ref = media.blob().ok_or(crate::Error::MissingBlob)?;
 
     let bytes = client
         .com
@@ -439,15 +644,53 @@ pub struct MessageFrame {
 
 #[trait_variant::make(HttpService: Send)]
 pub trait Subscription {
+    /// Wait for the next frame, or `None` once the subscription has ended
     async fn next(
         &mut self,
     ) -> Option<std::result::Result<Frame, <Frame as TryFrom<&[u8]>>::Error>>;
+
+    /// Re-establish the subscription after it has ended, resuming after the `seq` cursor.
+    ///
+    /// If `cursor` is `None`, the subscription resumes from where it started.
+    ///
+    /// By default, subscriptions cannot be resumed and fail with an
+    /// [`std::io::ErrorKind::Unsupported`] I/O error.
+    // Not an `async fn`, as `trait_variant` cannot generate default bodies for those
+    fn resume(
+        &mut self,
+        cursor: Option<i64>,
+    ) -> impl std::future::Future<Output = crate::Result<()>> {
+        let _ = cursor;
+        async {
+            Err(crate::Error::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "this subscription cannot be resumed",
+            )))
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// An error sent by the relay, after which it closes the connection.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ErrorFrame {
-    // TODO
-    // body: Value,
+    /// The error code, e.g. `FutureCursor` or `ConsumerTooSlow`
+    pub error: String,
+    /// A human-readable description of the error, if any
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl From<ErrorFrame> for crate::Error {
+    fn from(value: ErrorFrame) -> Self {
+        match value.error.as_str() {
+            "FutureCursor" => crate::Error::FutureCursor(value.message),
+            "ConsumerTooSlow" => crate::Error::ConsumerTooSlow(value.message),
+            _ => crate::Error::RelayError {
+                error: value.error,
+                message: value.message,
+            },
+        }
+    }
 }
 
 impl TryFrom<&[u8]> for Frame {
@@ -473,11 +716,42 @@ impl TryFrom<&[u8]> for Frame {
                 },
             ))
         } else {
-            Ok(Frame::Error(ErrorFrame {}))
+            Ok(Frame::Error(serde_ipld_dagcbor::from_slice(right)?))
         }
     }
 }
 
+impl Frame {
+    /// Build a message frame of type `t` (e.g. `#commit`), encoding `body` as DAG-CBOR
+    pub fn message(t: &str, body: &impl Serialize) -> Result<Self> {
+        Ok(Frame::Message(
+            Some(t.to_string()),
+            MessageFrame {
+                body: serde_ipld_dagcbor::to_vec(body)?,
+            },
+        ))
+    }
+
+    /// Encode the frame as it is sent over the websocket, a DAG-CBOR header followed by
+    /// the body. This is the reverse of [`Frame::try_from`].
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let (op, t) = match self {
+            Frame::Message(t, _) => (1, t.as_deref()),
+            Frame::Error(_) => (-1, None),
+        };
+        let mut header = std::collections::BTreeMap::from([("op".to_string(), Ipld::Integer(op))]);
+        if let Some(t) = t {
+            header.insert("t".to_string(), Ipld::String(t.to_string()));
+        }
+        let mut bytes = serde_ipld_dagcbor::to_vec(&Ipld::Map(header))?;
+        match self {
+            Frame::Message(_, message) => bytes.extend_from_slice(&message.body),
+            Frame::Error(error) => bytes.extend(serde_ipld_dagcbor::to_vec(error)?),
+        }
+        Ok(bytes)
+    }
+}
+
 pub struct CidOld(cid_old::Cid);
 
 impl From<cid_old::Cid> for CidOld {
@@ -506,7 +780,7 @@ mod tests {
     use super::*;
 
     fn serialized_data(s: &str) -> Vec<u8> {
-        assert!(s.len() % 2 == 0);
+        assert_eq!(s.len() % 2, 0);
         let b2u = |b: u8| match b {
             b'0'..=b'9' => b - b'0',
             b'a'..=b'f' => b - b'a' + 10,
@@ -539,6 +813,277 @@ mod tests {
         assert_eq!(result.expect("failed to deserialize"), FrameHeader::Error);
     }
 
+    #[test]
+    fn deserialize_error_frame() {
+        let header = serde_ipld_dagcbor::to_vec(&Ipld::Map(
+            [("op".to_string(), Ipld::Integer(-1))]
+                .into_iter()
+                .collect(),
+        ))
+        .unwrap();
+        let body = serde_ipld_dagcbor::to_vec(&Ipld::Map(
+            [
+                ("error".to_string(), Ipld::String("FutureCursor".into())),
+                (
+                    "message".to_string(),
+                    Ipld::String("cursor in the future".into()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        ))
+        .unwrap();
+        let frame = Frame::try_from([header, body].concat().as_slice()).expect("invalid frame");
+
+        let Frame::Error(error) = frame else {
+            panic!("expected an error frame, got {frame:?}");
+        };
+        assert_eq!(error.error, "FutureCursor");
+        assert!(matches!(
+            crate::Error::from(error),
+            crate::Error::FutureCursor(Some(message)) if message == "cursor in the future"
+        ));
+    }
+
+    #[test]
+    fn encode_frames() {
+        let frame = Frame::message("#info", &Ipld::Map(Default::default())).unwrap();
+        let bytes = frame.to_bytes().unwrap();
+        assert_eq!(Frame::try_from(bytes.as_slice()).unwrap(), frame);
+
+        let frame = Frame::Error(ErrorFrame {
+            error: "ConsumerTooSlow".to_string(),
+            message: None,
+        });
+        let bytes = frame.to_bytes().unwrap();
+        assert_eq!(Frame::try_from(bytes.as_slice()).unwrap(), frame);
+    }
+
+    #[test]
+    fn strong_ref_subject() {
+        let strong_ref = StrongRef {
+            uri: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l"
+                .to_string(),
+            cid: "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+                .parse()
+                .unwrap(),
+        };
+        assert_eq!(
+            strong_ref.did().unwrap().as_str(),
+            "did:plc:z72i7hdynmk6r22z27h6tvur"
+        );
+        assert_eq!(strong_ref.collection(), Some("app.bsky.feed.post"));
+        assert_eq!(strong_ref.at_uri().unwrap().rkey, "3l6oveex3ii2l");
+
+        let invalid = StrongRef {
+            uri: "not a uri".to_string(),
+            ..strong_ref
+        };
+        assert!(invalid.did().is_none());
+        assert!(invalid.collection().is_none());
+    }
+
+    fn post_record(
+        text: &str,
+        facets: Option<Vec<atrium_api::app::bsky::richtext::facet::Main>>,
+        embed: Option<RecordEmbedRefs>,
+    ) -> PostRecord {
+        atrium_api::app::bsky::feed::post::RecordData {
+            created_at: atrium_api::types::string::Datetime::now(),
+            embed: embed.map(atrium_api::types::Union::Refs),
+            entities: None,
+            facets,
+            labels: None,
+            langs: None,
+            reply: None,
+            tags: None,
+            text: text.to_string(),
+        }
+        .into()
+    }
+
+    fn post(record: PostRecord) -> Post {
+        Post::from_record(
+            "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+            CidLink(
+                "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+                    .parse()
+                    .unwrap(),
+            ),
+            record,
+            "3l6oveex3ii2l",
+        )
+    }
+
+    #[test]
+    fn post_facets() {
+        use atrium_api::app::bsky::richtext::facet;
+
+        let facet = |start, end, feature| -> facet::Main {
+            facet::MainData {
+                features: vec![atrium_api::types::Union::Refs(feature)],
+                index: facet::ByteSliceData {
+                    byte_start: start,
+                    byte_end: end,
+                }
+                .into(),
+            }
+            .into()
+        };
+        let text = "héllo @alice.bsky.social 🦀 #rust bsky.app";
+        let post = post(post_record(
+            text,
+            Some(vec![
+                facet(
+                    7,
+                    25,
+                    facet::MainFeaturesItem::Mention(Box::new(
+                        facet::MentionData {
+                            did: "did:plc:z72i7hdynmk6r22z27h6tvur".parse().unwrap(),
+                        }
+                        .into(),
+                    )),
+                ),
+                facet(
+                    31,
+                    36,
+                    facet::MainFeaturesItem::Tag(Box::new(
+                        facet::TagData {
+                            tag: "rust".to_string(),
+                        }
+                        .into(),
+                    )),
+                ),
+                facet(
+                    37,
+                    45,
+                    facet::MainFeaturesItem::Link(Box::new(
+                        facet::LinkData {
+                            uri: "https://bsky.app/".to_string(),
+                        }
+                        .into(),
+                    )),
+                ),
+            ]),
+            None,
+        ));
+
+        assert_eq!(
+            post.mentions().map(|did| did.as_str()).collect::<Vec<_>>(),
+            ["did:plc:z72i7hdynmk6r22z27h6tvur"]
+        );
+        assert_eq!(post.hashtags().collect::<Vec<_>>(), ["rust"]);
+        assert_eq!(post.links().collect::<Vec<_>>(), ["https://bsky.app/"]);
+        assert_eq!(
+            post.facets
+                .iter()
+                .map(|facet| post.facet_text(facet))
+                .collect::<Vec<_>>(),
+            [Some("@alice.bsky.social"), Some("#rust"), Some("bsky.app")]
+        );
+    }
+
+    #[test]
+    fn record_with_external_media() {
+        use atrium_api::{
+            app::bsky::embed::{external, record, record_with_media, video},
+            com::atproto::repo::strong_ref,
+            types::{Blob as ABlob, BlobRef, TypedBlobRef},
+        };
+
+        let cid: CidLink = CidLink(
+            "bafyreie5737gdxlw5i64vzichcalba3z2v5n6icifvx5xytvske7mr3hpm"
+                .parse()
+                .unwrap(),
+        );
+        let blob = |mime_type: &str| {
+            BlobRef::Typed(TypedBlobRef::Blob(ABlob {
+                r#ref: cid.clone(),
+                mime_type: mime_type.to_string(),
+                size: 1024,
+            }))
+        };
+        let quoted: record::Main = record::MainData {
+            record: strong_ref::MainData {
+                cid: atrium_api::types::string::Cid::new(cid.0),
+                uri: "at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l"
+                    .to_string(),
+            }
+            .into(),
+        }
+        .into();
+        let card: external::Main = external::MainData {
+            external: external::ExternalData {
+                description: "A link".to_string(),
+                thumb: Some(blob("image/jpeg")),
+                title: "Example".to_string(),
+                uri: "https://www.example.com/article".to_string(),
+            }
+            .into(),
+        }
+        .into();
+
+        let post = post(post_record(
+            "look at this",
+            None,
+            Some(RecordEmbedRefs::AppBskyEmbedRecordWithMediaMain(Box::new(
+                record_with_media::MainData {
+                    media: Union::Refs(record_with_media::MainMediaRefs::AppBskyEmbedExternalMain(
+                        Box::new(card),
+                    )),
+                    record: quoted.clone(),
+                }
+                .into(),
+            ))),
+        ));
+        let embed = post.embed.as_ref().unwrap();
+        assert!(matches!(embed, Embed::RecordWithMedia(..)));
+        assert_eq!(
+            embed.external().map(|link| link.uri.as_str()),
+            Some("https://www.example.com/article")
+        );
+        assert_eq!(
+            embed.record().map(|r| r.uri.as_str()),
+            Some("at://did:plc:z72i7hdynmk6r22z27h6tvur/app.bsky.feed.post/3l6oveex3ii2l")
+        );
+        assert!(post.get_post_media().is_empty());
+
+        let video = video::MainData {
+            alt: None,
+            aspect_ratio: None,
+            captions: Some(vec![video::CaptionData {
+                file: blob("text/vtt"),
+                lang: "ja".parse().unwrap(),
+            }
+            .into()]),
+            video: blob("video/mp4"),
+        };
+        let data = PostData::new(
+            post.author.clone(),
+            cid.clone(),
+            post_record(
+                "a video",
+                None,
+                Some(RecordEmbedRefs::AppBskyEmbedVideoMain(Box::new(
+                    video.into(),
+                ))),
+            ),
+            "3l6oveex3ii2l",
+        );
+        let media = data.get_media().unwrap();
+        let [Media::Video(video)] = media.as_slice() else {
+            panic!("expected a single video, got {media:?}");
+        };
+        assert_eq!(video.langs().collect::<Vec<_>>(), ["ja"]);
+        assert_eq!(
+            video.thumbnail_url(&post.author),
+            format!(
+                "https://video.bsky.app/watch/did:plc:z72i7hdynmk6r22z27h6tvur/{}/thumbnail.jpg",
+                cid.0
+            )
+        );
+    }
+
     #[test]
     fn deserialize_invalid_frame_header() {
         {
@@ -1,8 +1,40 @@
+use super::AtUri;
 use atrium_api::{
     app::bsky,
     com::atproto::sync::subscribe_repos::RepoOp,
-    types::{CidLink, Collection},
+    types::{string::Did, CidLink, Collection},
 };
+use serde::{Deserialize, Serialize};
+
+/// The kind of change an operation makes to a record.
+///
+/// Every record type carries the action of the operation it was decoded from in its
+/// `action` field: [`Action::Create`] or [`Action::Update`], as deleted records have no
+/// data to decode and become a [`super::commit::DeleteEvent`] instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// A new record was created
+    #[default]
+    Create,
+    /// An existing record was replaced
+    Update,
+    /// A record was deleted
+    Delete,
+    /// An action not (yet) known to the library
+    Unknown,
+}
+
+impl From<&str> for Action {
+    fn from(value: &str) -> Self {
+        match value {
+            "create" => Action::Create,
+            "update" => Action::Update,
+            "delete" => Action::Delete,
+            _ => Action::Unknown,
+        }
+    }
+}
 
 /// An operation in a commit.
 ///
@@ -23,6 +55,22 @@ pub enum Operation {
     ListItem(Option<CidLink>, RepoOp),
     // app.bsky.actor.profile
     Profile(Option<CidLink>, RepoOp),
+    // app.bsky.feed.generator
+    FeedGenerator(Option<CidLink>, RepoOp),
+    // app.bsky.feed.threadgate
+    Threadgate(Option<CidLink>, RepoOp),
+    // app.bsky.feed.postgate
+    Postgate(Option<CidLink>, RepoOp),
+    // app.bsky.graph.list
+    List(Option<CidLink>, RepoOp),
+    // app.bsky.graph.listblock
+    ListBlock(Option<CidLink>, RepoOp),
+    // app.bsky.graph.starterpack
+    StarterPack(Option<CidLink>, RepoOp),
+    // app.bsky.labeler.service
+    LabelerService(Option<CidLink>, RepoOp),
+    // chat.bsky.actor.declaration
+    ChatDeclaration(Option<CidLink>, RepoOp),
     // other stuff
     Other(String, Option<CidLink>, RepoOp),
 }
@@ -39,6 +87,14 @@ impl Operation {
             bsky::feed::Like::NSID => Operation::Like(cid, op),
             bsky::graph::Listitem::NSID => Operation::ListItem(cid, op),
             bsky::actor::Profile::NSID => Operation::Profile(cid, op),
+            bsky::feed::Generator::NSID => Operation::FeedGenerator(cid, op),
+            bsky::feed::Threadgate::NSID => Operation::Threadgate(cid, op),
+            bsky::feed::Postgate::NSID => Operation::Postgate(cid, op),
+            bsky::graph::List::NSID => Operation::List(cid, op),
+            bsky::graph::Listblock::NSID => Operation::ListBlock(cid, op),
+            bsky::graph::Starterpack::NSID => Operation::StarterPack(cid, op),
+            bsky::labeler::Service::NSID => Operation::LabelerService(cid, op),
+            atrium_api::chat::bsky::actor::Declaration::NSID => Operation::ChatDeclaration(cid, op),
             _ => Operation::Other(path.to_string(), cid, op),
         }
     }
@@ -53,10 +109,62 @@ impl Operation {
             | Operation::Like(cid, _)
             | Operation::ListItem(cid, _)
             | Operation::Profile(cid, _)
+            | Operation::FeedGenerator(cid, _)
+            | Operation::Threadgate(cid, _)
+            | Operation::Postgate(cid, _)
+            | Operation::List(cid, _)
+            | Operation::ListBlock(cid, _)
+            | Operation::StarterPack(cid, _)
+            | Operation::LabelerService(cid, _)
+            | Operation::ChatDeclaration(cid, _)
             | Operation::Other(_, cid, _) => cid.clone(),
         }
     }
 
+    /// Get the action of the operation, i.e. whether the record was created, updated or deleted
+    pub fn action(&self) -> Action {
+        self.op_ref().action.as_str().into()
+    }
+
+    /// Get the collection NSID of the record this operation affects
+    pub fn collection(&self) -> &str {
+        let path = self.op_ref().path.as_str();
+        path.split_once('/')
+            .map_or(path, |(collection, _)| collection)
+    }
+
+    /// Get the record key of the record this operation affects
+    pub fn rkey(&self) -> &str {
+        let path = self.op_ref().path.as_str();
+        path.split_once('/').map_or("", |(_, rkey)| rkey)
+    }
+
+    /// Get the AT-URI of the record this operation affects, given the repository DID
+    pub fn uri(&self, repo: &Did) -> AtUri {
+        AtUri::new(repo.clone(), self.collection(), self.rkey())
+    }
+
+    fn op_ref(&self) -> &RepoOp {
+        match self {
+            Operation::Post(_, op)
+            | Operation::Follow(_, op)
+            | Operation::Block(_, op)
+            | Operation::Repost(_, op)
+            | Operation::Like(_, op)
+            | Operation::ListItem(_, op)
+            | Operation::Profile(_, op)
+            | Operation::FeedGenerator(_, op)
+            | Operation::Threadgate(_, op)
+            | Operation::Postgate(_, op)
+            | Operation::List(_, op)
+            | Operation::ListBlock(_, op)
+            | Operation::StarterPack(_, op)
+            | Operation::LabelerService(_, op)
+            | Operation::ChatDeclaration(_, op)
+            | Operation::Other(_, _, op) => op,
+        }
+    }
+
     /// Get the operation type
     pub fn get_op(&self) -> RepoOp {
         match self {
@@ -67,7 +175,101 @@ impl Operation {
             | Operation::Like(_, op)
             | Operation::ListItem(_, op)
             | Operation::Profile(_, op)
+            | Operation::FeedGenerator(_, op)
+            | Operation::Threadgate(_, op)
+            | Operation::Postgate(_, op)
+            | Operation::List(_, op)
+            | Operation::ListBlock(_, op)
+            | Operation::StarterPack(_, op)
+            | Operation::LabelerService(_, op)
+            | Operation::ChatDeclaration(_, op)
             | Operation::Other(_, _, op) => op.clone(),
         }
     }
 }
+
+/// Serializes as the underlying [`RepoOp`], i.e. its `action`, `path` and `cid`
+impl Serialize for Operation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.op_ref().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Operation {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        RepoOp::deserialize(deserializer).map(Operation::from_op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atrium_api::com::atproto::sync::subscribe_repos::RepoOpData;
+
+    fn repo_op(action: &str, path: &str) -> RepoOp {
+        RepoOpData {
+            action: action.to_string(),
+            cid: None,
+            path: path.to_string(),
+        }
+        .into()
+    }
+
+    #[test]
+    fn operation_path_and_action() {
+        let op = Operation::from_op(repo_op("delete", "app.bsky.feed.like/3l6oveex3ii2l"));
+        assert!(matches!(op, Operation::Like(None, _)));
+        assert_eq!(op.action(), Action::Delete);
+        assert_eq!(op.collection(), "app.bsky.feed.like");
+        assert_eq!(op.rkey(), "3l6oveex3ii2l");
+
+        let op = Operation::from_op(repo_op("update", "app.bsky.actor.profile/self"));
+        assert_eq!(op.action(), Action::Update);
+        assert_eq!(op.rkey(), "self");
+
+        let op = Operation::from_op(repo_op("frobnicate", "com.example.record/abc"));
+        assert_eq!(op.action(), Action::Unknown);
+        assert!(matches!(op, Operation::Other(ref nsid, None, _) if nsid == "com.example.record"));
+    }
+
+    #[test]
+    fn operation_collections() {
+        type IsVariant = fn(&Operation) -> bool;
+        let cases: [(&str, &str, IsVariant); 8] = [
+            ("app.bsky.feed.generator", "whats-hot", |op| {
+                matches!(op, Operation::FeedGenerator(..))
+            }),
+            ("app.bsky.feed.threadgate", "3l6oveex3ii2l", |op| {
+                matches!(op, Operation::Threadgate(..))
+            }),
+            ("app.bsky.feed.postgate", "3l6oveex3ii2l", |op| {
+                matches!(op, Operation::Postgate(..))
+            }),
+            ("app.bsky.graph.list", "3l6oveex3ii2l", |op| {
+                matches!(op, Operation::List(..))
+            }),
+            ("app.bsky.graph.listblock", "3l6oveex3ii2l", |op| {
+                matches!(op, Operation::ListBlock(..))
+            }),
+            ("app.bsky.graph.starterpack", "3l6oveex3ii2l", |op| {
+                matches!(op, Operation::StarterPack(..))
+            }),
+            ("app.bsky.labeler.service", "self", |op| {
+                matches!(op, Operation::LabelerService(..))
+            }),
+            ("chat.bsky.actor.declaration", "self", |op| {
+                matches!(op, Operation::ChatDeclaration(..))
+            }),
+        ];
+        for (collection, rkey, is_expected) in cases {
+            let op = Operation::from_op(repo_op("create", &format!("{collection}/{rkey}")));
+            assert!(
+                is_expected(&op),
+                "{collection} mapped to the wrong operation"
+            );
+            assert_eq!(op.collection(), collection);
+            assert_eq!(op.rkey(), rkey);
+            assert_eq!(op.action(), Action::Create);
+        }
+    }
+}
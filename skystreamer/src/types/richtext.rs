@@ -0,0 +1,106 @@
+//! Rich text facets, annotating parts of a post's text as mentions, links or hashtags.
+//!
+//! Facets address the text by UTF-8 byte offsets, not characters, so always use
+//! [`Facet::slice`] to get the annotated text instead of indexing the string directly.
+use atrium_api::{
+    app::bsky::richtext::facet::{self, MainFeaturesItem},
+    types::{string::Did, Union},
+};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// What a facet annotates its part of the text as
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FacetFeature {
+    /// A mention of another account, usually written as its handle
+    Mention(Did),
+    /// A link to a URL. The text may be a shortened version of the URL.
+    Link(String),
+    /// A hashtag, without the leading `#`
+    Tag(String),
+}
+
+/// An annotation of a part of a post's text
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Facet {
+    /// The start of the annotated text, as an inclusive UTF-8 byte offset
+    pub byte_start: usize,
+    /// The end of the annotated text, as an exclusive UTF-8 byte offset
+    pub byte_end: usize,
+    pub features: Vec<FacetFeature>,
+}
+
+impl Facet {
+    /// The byte range of the annotated text
+    pub fn range(&self) -> Range<usize> {
+        self.byte_start..self.byte_end
+    }
+
+    /// Get the annotated part of `text`.
+    ///
+    /// Returns `None` if the range is out of bounds, or does not fall on character
+    /// boundaries, which happens if the facet was created with the wrong kind of offsets.
+    pub fn slice<'a>(&self, text: &'a str) -> Option<&'a str> {
+        text.get(self.range())
+    }
+}
+
+impl From<facet::Main> for Facet {
+    fn from(value: facet::Main) -> Self {
+        let index = value.data.index.data;
+        Self {
+            byte_start: index.byte_start,
+            byte_end: index.byte_end,
+            features: value
+                .data
+                .features
+                .into_iter()
+                .filter_map(|feature| match feature {
+                    Union::Refs(MainFeaturesItem::Mention(m)) => {
+                        Some(FacetFeature::Mention(m.data.did))
+                    }
+                    Union::Refs(MainFeaturesItem::Link(l)) => Some(FacetFeature::Link(l.data.uri)),
+                    Union::Refs(MainFeaturesItem::Tag(t)) => Some(FacetFeature::Tag(t.data.tag)),
+                    Union::Unknown(_) => None,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slice_multibyte_text() {
+        let text = "héllo @alice.bsky.social 🦀 #rust";
+        let mention = Facet {
+            byte_start: 7,
+            byte_end: 25,
+            features: vec![],
+        };
+        assert_eq!(mention.slice(text), Some("@alice.bsky.social"));
+
+        let tag = Facet {
+            byte_start: 31,
+            byte_end: 36,
+            features: vec![],
+        };
+        assert_eq!(tag.slice(text), Some("#rust"));
+
+        // Inside the crab emoji, and past the end of the text
+        let broken = Facet {
+            byte_start: 27,
+            byte_end: 29,
+            features: vec![],
+        };
+        assert_eq!(broken.slice(text), None);
+        let broken = Facet {
+            byte_start: 31,
+            byte_end: 40,
+            features: vec![],
+        };
+        assert_eq!(broken.slice(text), None);
+    }
+}
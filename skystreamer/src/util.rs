@@ -1,4 +1,7 @@
-use atrium_api::types::string::{Cid as ACid, Datetime};
+use atrium_api::{
+    com::atproto::label::defs::SelfLabels,
+    types::string::{Cid as ACid, Datetime},
+};
 use chrono::{DateTime, FixedOffset};
 use cid::Cid;
 
@@ -15,3 +18,275 @@ pub fn datetime_to_chrono(dt: &Datetime) -> DateTime<FixedOffset> {
 pub fn conv_atrium_cid(cid: &ACid) -> Cid {
     Cid::from(cid.as_ref())
 }
+
+/// Extract the label values from a record's self-labels
+#[inline]
+pub fn self_label_values(labels: &SelfLabels) -> Vec<String> {
+    labels.values.iter().map(|v| v.val.clone()).collect()
+}
+
+/// Serde helpers for fields of type [`Cid`], writing the CID in its string form.
+///
+/// The string form is only used by human-readable formats such as JSON, where the
+/// default encoding of a [`Cid`] is an array of raw bytes. Binary formats such as
+/// DAG-CBOR keep encoding it as a link.
+pub(crate) mod cid_string {
+    use cid::Cid;
+    use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(cid: &Cid, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(cid)
+        } else {
+            cid.serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Cid, D::Error> {
+        if deserializer.is_human_readable() {
+            String::deserialize(deserializer)?
+                .parse()
+                .map_err(D::Error::custom)
+        } else {
+            Cid::deserialize(deserializer)
+        }
+    }
+
+    /// [`cid_string`](self) for optional fields
+    pub mod option {
+        use cid::Cid;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            cid: &Option<Cid>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match cid {
+                Some(cid) => super::serialize(cid, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Cid>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Cid);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(cid)| cid))
+        }
+    }
+}
+
+/// Serializes the struct `value` without the fields named in `skip`.
+///
+/// Used to leave out fields written elsewhere, without buffering the struct. Fields are
+/// counted in a first pass, as formats like DAG-CBOR write the number of fields up front.
+/// Values other than structs fail to serialize.
+pub(crate) struct WithoutFields<'a, T: ?Sized> {
+    pub value: &'a T,
+    pub skip: &'a [&'a str],
+}
+
+impl<T: serde::Serialize + ?Sized> serde::Serialize for WithoutFields<'_, T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::Error as _;
+
+        let len = self
+            .value
+            .serialize(without_fields::Counter { skip: self.skip })
+            .map_err(S::Error::custom)?;
+        self.value.serialize(without_fields::Filter {
+            inner: serializer,
+            skip: self.skip,
+            len,
+        })
+    }
+}
+
+/// The serializers behind [`WithoutFields`]
+mod without_fields {
+    use serde::ser::{self, Impossible, Serialize, SerializeStruct, Serializer};
+
+    /// Implement every method of [`Serializer`] but `serialize_struct` as an error
+    macro_rules! only_structs {
+        ($($method:ident($($arg:ty),*) -> $ret:ident;)*) => {
+            $(
+                fn $method(self, $(_: $arg),*) -> Result<Self::$ret, Self::Error> {
+                    Err(ser::Error::custom("expected a struct"))
+                }
+            )*
+        };
+    }
+
+    macro_rules! only_structs_serializer {
+        () => {
+            only_structs! {
+                serialize_bool(bool) -> Ok;
+                serialize_i8(i8) -> Ok;
+                serialize_i16(i16) -> Ok;
+                serialize_i32(i32) -> Ok;
+                serialize_i64(i64) -> Ok;
+                serialize_u8(u8) -> Ok;
+                serialize_u16(u16) -> Ok;
+                serialize_u32(u32) -> Ok;
+                serialize_u64(u64) -> Ok;
+                serialize_f32(f32) -> Ok;
+                serialize_f64(f64) -> Ok;
+                serialize_char(char) -> Ok;
+                serialize_str(&str) -> Ok;
+                serialize_bytes(&[u8]) -> Ok;
+                serialize_none() -> Ok;
+                serialize_unit() -> Ok;
+                serialize_unit_struct(&'static str) -> Ok;
+                serialize_unit_variant(&'static str, u32, &'static str) -> Ok;
+                serialize_seq(Option<usize>) -> SerializeSeq;
+                serialize_tuple(usize) -> SerializeTuple;
+                serialize_tuple_struct(&'static str, usize) -> SerializeTupleStruct;
+                serialize_tuple_variant(&'static str, u32, &'static str, usize) -> SerializeTupleVariant;
+                serialize_map(Option<usize>) -> SerializeMap;
+                serialize_struct_variant(&'static str, u32, &'static str, usize) -> SerializeStructVariant;
+            }
+
+            fn serialize_some<T: Serialize + ?Sized>(self, _: &T) -> Result<Self::Ok, Self::Error> {
+                Err(ser::Error::custom("expected a struct"))
+            }
+
+            fn serialize_newtype_struct<T: Serialize + ?Sized>(
+                self,
+                _: &'static str,
+                _: &T,
+            ) -> Result<Self::Ok, Self::Error> {
+                Err(ser::Error::custom("expected a struct"))
+            }
+
+            fn serialize_newtype_variant<T: Serialize + ?Sized>(
+                self,
+                _: &'static str,
+                _: u32,
+                _: &'static str,
+                _: &T,
+            ) -> Result<Self::Ok, Self::Error> {
+                Err(ser::Error::custom("expected a struct"))
+            }
+        };
+    }
+
+    /// Counts the fields of a struct that are not skipped, without serializing them
+    pub(super) struct Counter<'a> {
+        pub skip: &'a [&'a str],
+    }
+
+    impl<'a> Serializer for Counter<'a> {
+        type Ok = usize;
+        type Error = serde::de::value::Error;
+        type SerializeSeq = Impossible<usize, Self::Error>;
+        type SerializeTuple = Impossible<usize, Self::Error>;
+        type SerializeTupleStruct = Impossible<usize, Self::Error>;
+        type SerializeTupleVariant = Impossible<usize, Self::Error>;
+        type SerializeMap = Impossible<usize, Self::Error>;
+        type SerializeStruct = Count<'a>;
+        type SerializeStructVariant = Impossible<usize, Self::Error>;
+
+        only_structs_serializer!();
+
+        fn serialize_struct(
+            self,
+            _: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStruct, Self::Error> {
+            Ok(Count {
+                skip: self.skip,
+                len: 0,
+            })
+        }
+    }
+
+    pub(super) struct Count<'a> {
+        skip: &'a [&'a str],
+        len: usize,
+    }
+
+    impl SerializeStruct for Count<'_> {
+        type Ok = usize;
+        type Error = serde::de::value::Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            _: &T,
+        ) -> Result<(), Self::Error> {
+            if !self.skip.contains(&key) {
+                self.len += 1;
+            }
+            Ok(())
+        }
+
+        fn end(self) -> Result<usize, Self::Error> {
+            Ok(self.len)
+        }
+    }
+
+    /// Passes a struct on to `inner`, without the skipped fields
+    pub(super) struct Filter<'a, S> {
+        pub inner: S,
+        pub skip: &'a [&'a str],
+        /// The number of fields left, from [`Counter`]
+        pub len: usize,
+    }
+
+    impl<'a, S: Serializer> Serializer for Filter<'a, S> {
+        type Ok = S::Ok;
+        type Error = S::Error;
+        type SerializeSeq = Impossible<S::Ok, S::Error>;
+        type SerializeTuple = Impossible<S::Ok, S::Error>;
+        type SerializeTupleStruct = Impossible<S::Ok, S::Error>;
+        type SerializeTupleVariant = Impossible<S::Ok, S::Error>;
+        type SerializeMap = Impossible<S::Ok, S::Error>;
+        type SerializeStruct = Filtered<'a, S::SerializeStruct>;
+        type SerializeStructVariant = Impossible<S::Ok, S::Error>;
+
+        only_structs_serializer!();
+
+        fn is_human_readable(&self) -> bool {
+            self.inner.is_human_readable()
+        }
+
+        fn serialize_struct(
+            self,
+            name: &'static str,
+            _: usize,
+        ) -> Result<Self::SerializeStruct, Self::Error> {
+            Ok(Filtered {
+                inner: self.inner.serialize_struct(name, self.len)?,
+                skip: self.skip,
+            })
+        }
+    }
+
+    pub(super) struct Filtered<'a, S> {
+        inner: S,
+        skip: &'a [&'a str],
+    }
+
+    impl<S: SerializeStruct> SerializeStruct for Filtered<'_, S> {
+        type Ok = S::Ok;
+        type Error = S::Error;
+
+        fn serialize_field<T: Serialize + ?Sized>(
+            &mut self,
+            key: &'static str,
+            value: &T,
+        ) -> Result<(), Self::Error> {
+            if self.skip.contains(&key) {
+                Ok(())
+            } else {
+                self.inner.serialize_field(key, value)
+            }
+        }
+
+        fn end(self) -> Result<S::Ok, S::Error> {
+            self.inner.end()
+        }
+    }
+}
@@ -0,0 +1,437 @@
+//! Opt-in verification of commits against the repository's signing key.
+//!
+//! Relays pass commits through as they receive them from PDSes, so a consumer that
+//! does not trust its relay can check each commit itself. A [`CommitVerifier`]:
+//!
+//! - decodes the signed commit object from the commit's CAR slice and checks that it
+//!   belongs to the commit's repository,
+//! - verifies its signature against the repository's signing key, looked up with a
+//!   [`KeyResolver`],
+//! - walks the repository's Merkle Search Tree (MST) in the CAR slice, checking that
+//!   every created or updated record is present with the CID of its operation, and
+//!   that every deleted record is absent.
+//!
+//! Resolving keys requires resolving DIDs, which this library does not do itself.
+//! Implement [`KeyResolver`] on top of a DID resolver (and a cache), or use a
+//! [`StaticKeyResolver`] when the keys are known in advance.
+//!
+//! # Example
+//! ```no_run
+//! use skystreamer::{
+//!     stream::EventStream,
+//!     verify::{CommitVerifier, PublicKey, StaticKeyResolver},
+//!     RepoSubscription,
+//! };
+//!
+//! let key = PublicKey::from_did_key("did:key:zQ3shXjHeiBuRCKmM36cuYnm7YEMzhGnCmCyW92sRJ9pribSF")?;
+//! let resolver = StaticKeyResolver::new().with_key("did:plc:z72i7hdynmk6r22z27h6tvur", key);
+//! let subscription = RepoSubscription::new("bsky.network").await.unwrap();
+//! let mut binding = EventStream::new(subscription).with_verifier(CommitVerifier::new(resolver));
+//! ```
+use crate::{types::commit::BlockMap, Error, Result};
+use atrium_api::{com::atproto::sync::subscribe_repos::Commit as ACommit, types::string::Did};
+use cid::Cid;
+use futures::future::BoxFuture;
+use ipld_core::ipld::Ipld;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt, sync::Arc};
+
+/// Multicodec prefix of a compressed secp256k1 public key
+const SECP256K1_PUB: [u8; 2] = [0xe7, 0x01];
+/// Multicodec prefix of a compressed P-256 public key
+const P256_PUB: [u8; 2] = [0x80, 0x24];
+
+/// Why a commit failed verification
+#[derive(thiserror::Error, Debug)]
+pub enum VerificationError {
+    #[error("Block {0} is missing from the commit's CAR slice")]
+    MissingBlock(Cid),
+    #[error("Block {cid} is malformed: {reason}")]
+    InvalidBlock { cid: Cid, reason: String },
+    #[error("Commit object belongs to {found}, not {expected}")]
+    RepoMismatch { expected: String, found: String },
+    #[error("Invalid signing key: {0}")]
+    InvalidKey(String),
+    #[error("No signing key known for {0}")]
+    UnknownKey(String),
+    #[error("Commit signature does not match the repository's signing key")]
+    InvalidSignature,
+    #[error("Operation {path} does not match the MST: expected {expected:?}, found {found:?}")]
+    OpMismatch {
+        path: String,
+        expected: Option<Cid>,
+        found: Option<Cid>,
+    },
+}
+
+/// A repository signing key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PublicKey {
+    /// A secp256k1 (K-256) key, as used by most PDSes
+    K256(k256::ecdsa::VerifyingKey),
+    /// A NIST P-256 key
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Parse a `did:key` DID, such as the `atproto` verification method of a DID document.
+    ///
+    /// The `did:key:` prefix is optional, so the `publicKeyMultibase` of a
+    /// verification method can be passed as is.
+    pub fn from_did_key(did_key: &str) -> Result<Self> {
+        let multibase = did_key.strip_prefix("did:key:").unwrap_or(did_key);
+        let (_, bytes) = multibase::decode(multibase)
+            .map_err(|e| VerificationError::InvalidKey(e.to_string()))?;
+        let key = if let Some(point) = bytes.strip_prefix(&SECP256K1_PUB) {
+            k256::ecdsa::VerifyingKey::from_sec1_bytes(point).map(Self::K256)
+        } else if let Some(point) = bytes.strip_prefix(&P256_PUB) {
+            p256::ecdsa::VerifyingKey::from_sec1_bytes(point).map(Self::P256)
+        } else {
+            return Err(VerificationError::InvalidKey("unsupported key type".to_string()).into());
+        };
+        Ok(key.map_err(|e| VerificationError::InvalidKey(e.to_string()))?)
+    }
+
+    /// Encode the key as a `did:key` DID
+    pub fn to_did_key(&self) -> String {
+        let mut bytes = vec![];
+        match self {
+            Self::K256(key) => {
+                bytes.extend(SECP256K1_PUB);
+                bytes.extend(key.to_encoded_point(true).as_bytes());
+            }
+            Self::P256(key) => {
+                bytes.extend(P256_PUB);
+                bytes.extend(key.to_encoded_point(true).as_bytes());
+            }
+        }
+        format!(
+            "did:key:{}",
+            multibase::encode(multibase::Base::Base58Btc, bytes)
+        )
+    }
+
+    /// Verify a 64-byte compact ECDSA signature over `message`, hashed with SHA-256.
+    ///
+    /// Signatures with a high S value are rejected, as required by the AT Protocol.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        use k256::ecdsa::signature::Verifier;
+        match self {
+            Self::K256(key) => k256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|sig| key.verify(message, &sig).is_ok()),
+            Self::P256(key) => p256::ecdsa::Signature::from_slice(signature)
+                .is_ok_and(|sig| sig.normalize_s().is_none() && key.verify(message, &sig).is_ok()),
+        }
+    }
+}
+
+/// Looks up the current signing key of a repository.
+///
+/// Keys can be rotated, so implementations should not cache them forever.
+pub trait KeyResolver: Send + Sync {
+    /// Resolve the signing key of the repository `did`
+    fn resolve<'a>(&'a self, did: &'a Did) -> BoxFuture<'a, Result<PublicKey>>;
+}
+
+/// A [`KeyResolver`] with a fixed set of keys, e.g. for tests or a known set of repositories.
+#[derive(Debug, Clone, Default)]
+pub struct StaticKeyResolver {
+    keys: HashMap<String, PublicKey>,
+}
+
+impl StaticKeyResolver {
+    /// Create a resolver without any keys
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the signing key of the repository `did`, replacing any previous one
+    pub fn with_key(mut self, did: &str, key: PublicKey) -> Self {
+        self.keys.insert(did.to_string(), key);
+        self
+    }
+}
+
+impl KeyResolver for StaticKeyResolver {
+    fn resolve<'a>(&'a self, did: &'a Did) -> BoxFuture<'a, Result<PublicKey>> {
+        let key = self
+            .keys
+            .get(did.as_str())
+            .cloned()
+            .ok_or_else(|| VerificationError::UnknownKey(did.as_str().to_string()).into());
+        Box::pin(futures::future::ready(key))
+    }
+}
+
+/// A node of a repository's Merkle Search Tree
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MstNode {
+    /// The subtree of keys sorting before the first entry
+    pub l: Option<Cid>,
+    pub e: Vec<MstEntry>,
+}
+
+/// An entry of an MST node
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct MstEntry {
+    /// How many bytes of the previous entry's key this entry's key shares
+    pub p: usize,
+    /// The rest of the key, after the shared prefix
+    #[serde(with = "serde_bytes")]
+    pub k: Vec<u8>,
+    /// The CID of the record
+    pub v: Cid,
+    /// The subtree of keys sorting between this entry and the next
+    pub t: Option<Cid>,
+}
+
+/// Verifies commits against the signing key and MST of their repository.
+///
+/// Both checks are enabled by default.
+#[derive(Clone)]
+pub struct CommitVerifier {
+    resolver: Arc<dyn KeyResolver>,
+    signatures: bool,
+    proofs: bool,
+}
+
+impl fmt::Debug for CommitVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CommitVerifier")
+            .field("signatures", &self.signatures)
+            .field("proofs", &self.proofs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CommitVerifier {
+    /// Create a verifier looking up signing keys with `resolver`
+    pub fn new(resolver: impl KeyResolver + 'static) -> Self {
+        Self {
+            resolver: Arc::new(resolver),
+            signatures: true,
+            proofs: true,
+        }
+    }
+
+    /// Whether to verify the signature of the commit object
+    pub fn signatures(mut self, enabled: bool) -> Self {
+        self.signatures = enabled;
+        self
+    }
+
+    /// Whether to verify that the operations of the commit match its MST
+    pub fn proofs(mut self, enabled: bool) -> Self {
+        self.proofs = enabled;
+        self
+    }
+
+    /// Verify a commit, decoding its CAR slice.
+    pub async fn verify(&self, commit: &ACommit) -> Result<()> {
+        let blocks = BlockMap::from_car(&commit.blocks).await?;
+        self.verify_blocks(commit, &blocks).await
+    }
+
+    /// Verify a commit against its already decoded blocks.
+    pub async fn verify_blocks(&self, commit: &ACommit, blocks: &BlockMap) -> Result<()> {
+        let root = decode_commit_object(commit, blocks)?;
+        if self.signatures {
+            let key = self.resolver.resolve(&commit.repo).await?;
+            if !key.verify(&root.unsigned, &root.sig) {
+                return Err(VerificationError::InvalidSignature.into());
+            }
+        }
+        if self.proofs {
+            for op in &commit.ops {
+                let expected = op.cid.as_ref().map(|cid| cid.0);
+                let found = mst_lookup(blocks, root.data, op.path.as_bytes())?;
+                if found != expected {
+                    return Err(VerificationError::OpMismatch {
+                        path: op.path.clone(),
+                        expected,
+                        found,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The parts of a signed commit object needed for verification
+struct CommitObject {
+    /// The root of the repository's MST
+    data: Cid,
+    /// The DAG-CBOR encoding of the commit object without its signature
+    unsigned: Vec<u8>,
+    sig: Vec<u8>,
+}
+
+fn decode_commit_object(commit: &ACommit, blocks: &BlockMap) -> Result<CommitObject> {
+    let cid = commit.commit.0;
+    let invalid = |reason: &str| VerificationError::InvalidBlock {
+        cid,
+        reason: reason.to_string(),
+    };
+    let block = blocks
+        .get(&cid)
+        .ok_or(VerificationError::MissingBlock(cid))?;
+    let Ipld::Map(mut object) = serde_ipld_dagcbor::from_slice(block)? else {
+        return Err(invalid("commit object is not a map").into());
+    };
+
+    match object.get("did") {
+        Some(Ipld::String(did)) if did == commit.repo.as_str() => {}
+        Some(Ipld::String(did)) => {
+            return Err(VerificationError::RepoMismatch {
+                expected: commit.repo.as_str().to_string(),
+                found: did.clone(),
+            }
+            .into())
+        }
+        _ => return Err(invalid("missing did").into()),
+    }
+    let Some(Ipld::Link(data)) = object.get("data") else {
+        return Err(invalid("missing MST root").into());
+    };
+    let data = *data;
+    let Some(Ipld::Bytes(sig)) = object.remove("sig") else {
+        return Err(invalid("missing signature").into());
+    };
+
+    Ok(CommitObject {
+        data,
+        unsigned: serde_ipld_dagcbor::to_vec(&Ipld::Map(object))?,
+        sig,
+    })
+}
+
+/// Look up the CID stored under `key` in the MST rooted at `root`.
+///
+/// Only the nodes on the path to `key` are visited, so this works on the partial
+/// trees included in firehose commits.
+pub(crate) fn mst_lookup(blocks: &BlockMap, root: Cid, key: &[u8]) -> Result<Option<Cid>> {
+    let mut next = Some(root);
+    while let Some(cid) = next {
+        let block = blocks
+            .get(&cid)
+            .ok_or(VerificationError::MissingBlock(cid))?;
+        let node: MstNode = serde_ipld_dagcbor::from_slice(block)?;
+
+        let mut subtree = node.l;
+        let mut entry_key: Vec<u8> = vec![];
+        next = None;
+        let mut descended = false;
+        for entry in node.e {
+            if entry.p > entry_key.len() {
+                return Err(VerificationError::InvalidBlock {
+                    cid,
+                    reason: "key prefix longer than previous key".to_string(),
+                }
+                .into());
+            }
+            entry_key.truncate(entry.p);
+            entry_key.extend(entry.k);
+            match key.cmp(&entry_key) {
+                std::cmp::Ordering::Equal => return Ok(Some(entry.v)),
+                std::cmp::Ordering::Less => {
+                    next = subtree;
+                    descended = true;
+                    break;
+                }
+                std::cmp::Ordering::Greater => subtree = entry.t,
+            }
+        }
+        if !descended {
+            next = subtree;
+        }
+    }
+    Ok(None)
+}
+
+impl From<VerificationError> for Error {
+    fn from(value: VerificationError) -> Self {
+        Error::Verification(Box::new(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::CommitBuilder;
+    use k256::ecdsa::SigningKey;
+
+    const REPO: &str = "did:plc:z72i7hdynmk6r22z27h6tvur";
+
+    /// Build a signed commit creating one record per path
+    fn signed_commit(key: &SigningKey, paths: &[&str]) -> ACommit {
+        paths
+            .iter()
+            .fold(
+                CommitBuilder::new(REPO.parse().unwrap()).signing_key(key.clone()),
+                |builder, path| {
+                    let (collection, rkey) = path.split_once('/').unwrap();
+                    builder.create(collection, rkey, &Ipld::String(path.to_string()))
+                },
+            )
+            .build()
+    }
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).unwrap()
+    }
+
+    fn resolver(key: &SigningKey) -> StaticKeyResolver {
+        StaticKeyResolver::new().with_key(REPO, PublicKey::K256(*key.verifying_key()))
+    }
+
+    #[test]
+    fn did_key_round_trip() {
+        let key = PublicKey::K256(*signing_key().verifying_key());
+        let did_key = key.to_did_key();
+        assert!(did_key.starts_with("did:key:zQ3s"));
+        assert_eq!(PublicKey::from_did_key(&did_key).unwrap(), key);
+        assert!(PublicKey::from_did_key(
+            "did:key:z6MkhaXgBZDvotDkL5257faiztiGiC2QtKLGpbnnEGta2doK"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn verify_signed_commit() {
+        let key = signing_key();
+        let paths = [
+            "app.bsky.feed.like/3l6oveex3ii2l",
+            "app.bsky.feed.post/3l6oveex3ii2l",
+        ];
+        let commit = signed_commit(&key, &paths);
+        let verifier = CommitVerifier::new(resolver(&key));
+        futures::executor::block_on(verifier.verify(&commit)).unwrap();
+
+        // Signed by someone else
+        let other = SigningKey::from_slice(&[8; 32]).unwrap();
+        let result =
+            futures::executor::block_on(CommitVerifier::new(resolver(&other)).verify(&commit));
+        assert!(
+            matches!(result, Err(Error::Verification(ref e)) if matches!(**e, VerificationError::InvalidSignature)),
+            "{result:?}"
+        );
+        // ...but the MST still proves the operations
+        futures::executor::block_on(
+            CommitVerifier::new(resolver(&other))
+                .signatures(false)
+                .verify(&commit),
+        )
+        .unwrap();
+
+        // An operation that is not in the MST
+        let mut tampered = commit.clone();
+        tampered.data.ops[0].data.path = "app.bsky.feed.like/3l6oveex3ii2m".to_string();
+        let result = futures::executor::block_on(verifier.verify(&tampered));
+        assert!(
+            matches!(result, Err(Error::Verification(ref e)) if matches!(**e, VerificationError::OpMismatch { found: None, .. })),
+            "{result:?}"
+        );
+    }
+}